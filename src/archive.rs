@@ -0,0 +1,178 @@
+//! 直接读取 `.tar`/`.tar.gz` 中的书籍，无需先解压到磁盘
+//!
+//! tar（尤其是 gzip 压缩后）不能随机访问，成员只能按顺序读出。发现文件时只列出成员，
+//! 统计时再顺序读一遍，每读出一个成员就交给统计线程，不会同时把所有成员留在内存里；
+//! `zip::ZipArchive` 需要 `Read + Seek`，因此单个成员仍会整体读入内存。
+
+use std::fs::File;
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{BufReader, Read};
+use std::ops::ControlFlow;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use tar::Archive;
+
+use crate::walk::has_extension;
+
+
+/// tar 中的一个成员
+pub struct Member
+{
+    /// 成员在 tar 中的路径
+    pub name: String,
+    /// tar 头中记录的大小
+    pub size: u64,
+    /// 内容的 [`content_digest`]，只在列出成员时要求计算才有
+    pub digest: Option<u64>
+}
+
+
+/// 判断路径是否为 `.tar`、`.tar.gz` 或 `.tgz`
+pub fn is_tar<P: AsRef<Path>>(path: P) -> bool
+{
+    let name = path.as_ref()
+        .file_name()
+        .map(|n| n.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+
+/// 流式计算内容的哈希，用于找出内容相同的文件
+pub fn content_digest<R: Read>(mut reader: R) -> std::io::Result<u64>
+{
+    let mut hasher = DefaultHasher::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.write(&buf[..n]);
+    }
+}
+
+
+/// 成员在 tar 中的相对路径
+///
+/// 只保留普通的路径组件，去掉开头的 `/`、盘符与 `..`，拼接到 tar 的路径后不会指向 tar 之外。
+fn member_name(path: &Path) -> String
+{
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .into_owned()
+}
+
+
+/// 按顺序遍历 tar 中扩展名属于 `extensions`（不区分大小写）的普通文件
+///
+/// `each` 收到成员的路径、大小与内容的读取器，不读取内容时 tar 会自动跳过；返回 `Break` 时停止遍历。
+fn for_each_member<F>(path: &Path, extensions: &[String], mut each: F) -> Result<()>
+where
+    F: FnMut(String, u64, &mut dyn Read) -> Result<ControlFlow<()>>
+{
+    let file = BufReader::new(File::open(path).context("打开文件失败")?);
+    let lower = path.to_string_lossy().to_ascii_lowercase();
+    let reader: Box<dyn Read> = if lower.ends_with(".tar") {
+        Box::new(file)
+    } else {
+        Box::new(GzDecoder::new(file))
+    };
+
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries().context("读取tar文件时出现错误")? {
+        let mut entry = entry.context("读取tar成员时出现错误")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = member_name(&entry.path().context("tar成员路径无效")?);
+        if !has_extension(Path::new(&name), extensions) {
+            continue;
+        }
+        let size = entry.size();
+        if each(name, size, &mut entry)?.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+
+/// 列出 tar 中扩展名属于 `extensions` 的全部普通文件，不保留内容
+///
+/// `digest` 时顺便计算每个成员内容的哈希（需要解压全部内容）。
+pub fn tar_members<P: AsRef<Path>>(path: P, extensions: &[String], digest: bool) -> Result<Vec<Member>>
+{
+    let mut members = Vec::new();
+    for_each_member(path.as_ref(), extensions, |name, size, entry| {
+        let digest = if digest {
+            Some(content_digest(entry).with_context(|| format!("读取tar成员 {} 时出现错误", name))?)
+        } else {
+            None
+        };
+        members.push(Member { name, size, digest });
+        Ok(ControlFlow::Continue(()))
+    })?;
+    Ok(members)
+}
+
+
+/// 按顺序读取 [`tar_members`] 返回的第 `wanted` 个成员（序号须递增）
+///
+/// `each` 收到成员的序号、大小与内容的读取器，处理完一个才会读取下一个；返回 `Break` 时停止读取。
+pub fn read_tar_members<P, F>(path: P, extensions: &[String], wanted: &[usize], mut each: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(usize, u64, &mut dyn Read) -> Result<ControlFlow<()>>
+{
+    let mut wanted = wanted.iter().copied().peekable();
+    let mut ordinal = 0;
+    for_each_member(path.as_ref(), extensions, |_, size, entry| {
+        let current = ordinal;
+        ordinal += 1;
+        if wanted.next_if_eq(&current).is_none() {
+            return Ok(ControlFlow::Continue(()));
+        }
+        if each(current, size, entry)?.is_break() || wanted.peek().is_none() {
+            return Ok(ControlFlow::Break(()));
+        }
+        Ok(ControlFlow::Continue(()))
+    })
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn member_names_stay_inside_the_archive()
+    {
+        assert_eq!(member_name(Path::new("/abs/book.epub")), "abs/book.epub");
+        assert_eq!(member_name(Path::new("../../up.epub")), "up.epub");
+        assert_eq!(member_name(Path::new("a/./b/../c.epub")), "a/b/c.epub");
+
+        // `Header::set_path` 拒绝这样的路径，直接写入头部
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ["/abs/book.epub", "../up.epub"] {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(4);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            builder.append(&header, &b"data"[..]).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("epub-count-{}-members.tar", std::process::id()));
+        std::fs::write(&path, builder.into_inner().unwrap()).unwrap();
+        let members = tar_members(&path, &["epub".to_string()], false);
+        std::fs::remove_file(&path).unwrap();
+
+        let names: Vec<String> = members.unwrap().into_iter().map(|m| m.name).collect();
+        assert_eq!(names, ["abs/book.epub", "up.epub"]);
+    }
+}
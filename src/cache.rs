@@ -0,0 +1,88 @@
+//! `--cache` 使用的字数缓存
+//!
+//! 以文件的绝对路径为键，记录上次统计时的修改时间、大小与字数。
+//! 修改时间和大小都没变的文件直接使用缓存中的字数，不再解析。
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Serialize, Deserialize)]
+struct Entry
+{
+    /// 修改时间，自 UNIX 纪元起的纳秒数
+    mtime: u64,
+    size: u64,
+    word_count: u64
+}
+
+
+pub struct Cache
+{
+    path: PathBuf,
+    entries: HashMap<String, Entry>
+}
+
+
+/// 缓存的键与文件当前的修改时间、大小，无法读取元数据时返回 `None`
+fn stat(path: &Path) -> Option<(String, u64, u64)>
+{
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64;
+    let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    Some((key.to_string_lossy().into_owned(), mtime, metadata.len()))
+}
+
+
+impl Cache
+{
+    /// 空缓存，结束时仍会写入 `path`
+    pub fn empty(path: PathBuf) -> Cache
+    {
+        Cache { path, entries: HashMap::new() }
+    }
+
+    /// 读取缓存文件，文件不存在时返回空缓存
+    pub fn load(path: PathBuf) -> Result<Cache>
+    {
+        let entries = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("解析缓存文件 {} 时出现错误", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Cache::empty(path)),
+            Err(e) => {
+                return Err(e).with_context(|| format!("读取缓存文件 {} 时出现错误", path.display()));
+            }
+        };
+        Ok(Cache { path, entries })
+    }
+
+    /// 文件自上次统计后没有变化时返回缓存的字数
+    pub fn get(&self, path: &Path) -> Option<u64>
+    {
+        let (key, mtime, size) = stat(path)?;
+        self.entries.get(&key)
+            .filter(|e| e.mtime == mtime && e.size == size)
+            .map(|e| e.word_count)
+    }
+
+    /// 记录文件当前的修改时间、大小与字数
+    pub fn insert(&mut self, path: &Path, word_count: u64)
+    {
+        if let Some((key, mtime, size)) = stat(path) {
+            self.entries.insert(key, Entry { mtime, size, word_count });
+        }
+    }
+
+    /// 写回缓存文件，其他目录的条目原样保留
+    pub fn save(&self) -> Result<()>
+    {
+        let content = serde_json::to_string(&self.entries).context("序列化缓存时出现错误")?;
+        fs::write(&self.path, content)
+            .with_context(|| format!("写入缓存文件 {} 时出现错误", self.path.display()))
+    }
+}
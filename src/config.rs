@@ -0,0 +1,243 @@
+//! TOML 配置文件
+//!
+//! 优先级：命令行参数 > 配置文件 > 内置默认值。
+//! 配置文件的键与命令行参数同名，只是用下划线代替连字符，例如：
+//!
+//! ```toml
+//! cpu_nums = 4
+//! format = "json"
+//! exclude = ["*_sample.epub"]
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory};
+use serde::Deserialize;
+
+use crate::{Cli, CountBy, CountModeArg, OutputFormat, SortOrder};
+
+
+/// 配置文件的内容，未出现的键为 `None`
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config
+{
+    walk: Option<bool>,
+    stream_output: Option<bool>,
+    cpu_nums: Option<usize>,
+    threads_auto_cap: Option<bool>,
+    format: Option<OutputFormat>,
+    json_pretty: Option<bool>,
+    sort: Option<SortOrder>,
+    no_progress: Option<bool>,
+    quiet: Option<bool>,
+    reading_speed: Option<u64>,
+    reading_speed_cjk: Option<u64>,
+    include_txt: Option<bool>,
+    ext: Option<Vec<String>>,
+    by_chapter: Option<bool>,
+    show_title: Option<bool>,
+    min_words: Option<u64>,
+    max_words: Option<u64>,
+    filtered_total: Option<bool>,
+    exclude: Option<Vec<String>>,
+    max_depth: Option<usize>,
+    after: Option<String>,
+    before: Option<String>,
+    follow_symlinks: Option<bool>,
+    parallel_walk: Option<bool>,
+    no_ignore: Option<bool>,
+    no_group: Option<bool>,
+    max_entry_size: Option<u64>,
+    max_total_size: Option<u64>,
+    max_memory: Option<u64>,
+    no_mmap: Option<bool>,
+    verbose: Option<bool>,
+    unique: Option<bool>,
+    top_words: Option<usize>,
+    segment: Option<bool>,
+    stats: Option<bool>,
+    histogram: Option<bool>,
+    buckets: Option<Vec<u64>>,
+    group_by_dir: Option<bool>,
+    group_by_author: Option<bool>,
+    author: Option<String>,
+    cache: Option<PathBuf>,
+    output: Option<PathBuf>,
+    append: Option<bool>,
+    diff: Option<PathBuf>,
+    strict: Option<bool>,
+    warn_empty: Option<bool>,
+    max_name_width: Option<usize>,
+    count: Option<CountBy>,
+    count_mode: Option<CountModeArg>,
+    keep_ruby: Option<bool>,
+    sample: Option<usize>,
+    seed: Option<u64>,
+    percent: Option<bool>,
+    paths: Option<bool>,
+    timing: Option<bool>,
+    detect_lang: Option<bool>,
+    fail_fast: Option<bool>,
+    live_total: Option<usize>,
+    dedup: Option<bool>,
+    chapter_filter: Option<String>,
+    chapters: Option<String>,
+    password: Option<String>,
+    content_selector: Option<String>,
+    exclude_front_matter: Option<bool>,
+    include_head: Option<bool>,
+    include_punctuation: Option<bool>,
+    count_images: Option<bool>,
+    structure: Option<bool>,
+    chapter_count: Option<bool>,
+    script_breakdown: Option<bool>,
+    count_metadata: Option<bool>,
+    encoding: Option<String>,
+    retries: Option<u32>,
+    timeout: Option<u64>,
+    max_download: Option<u64>,
+    /// 配置文件中只能给出具体的目录
+    relative_to: Option<PathBuf>
+}
+
+
+/// 默认配置文件路径：`~/.config/epub-count/config.toml`
+pub fn default_path() -> Option<PathBuf>
+{
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".config").join("epub-count").join("config.toml"))
+}
+
+
+/// 读取配置文件
+///
+/// 显式指定的文件（`explicit`）不存在时报错；默认位置的文件不存在时返回空配置。
+pub fn load(path: Option<PathBuf>) -> Result<Config>
+{
+    let explicit = path.is_some();
+    let Some(path) = path.or_else(default_path) else {
+        return Ok(Config::default());
+    };
+    if !explicit && !path.exists() {
+        return Ok(Config::default());
+    }
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("读取配置文件 {} 时出现错误", path.display()))?;
+    toml::from_str(&content)
+        .with_context(|| format!("解析配置文件 {} 时出现错误", path.display()))
+}
+
+
+impl Config
+{
+    /// 把配置合并进命令行参数，命令行上显式给出的参数保持不变
+    ///
+    /// 配置文件中的值不经过 clap 的校验，合并后按命令行的规则重新检查一遍。
+    pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) -> Result<(), clap::Error>
+    {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        macro_rules! merge {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if let Some(value) = self.$field {
+                        if !from_cli(stringify!($field)) {
+                            cli.$field = value.into();
+                        }
+                    }
+                )*
+            };
+        }
+
+        merge!(
+            walk, stream_output, cpu_nums, threads_auto_cap, format, json_pretty, sort, no_progress, quiet, reading_speed, reading_speed_cjk,
+            include_txt, ext, by_chapter, show_title, min_words, max_words, filtered_total, exclude,
+            max_depth, after, before, follow_symlinks, parallel_walk, no_ignore, no_group, max_entry_size, max_total_size, max_memory, no_mmap,
+            verbose, unique, top_words, segment, stats, histogram, buckets, group_by_dir, group_by_author, author, cache, output, append, diff, strict, warn_empty, max_name_width, count, count_mode, keep_ruby, sample, seed, percent, paths, timing, detect_lang, fail_fast, live_total, dedup, chapter_filter, chapters, password, content_selector, exclude_front_matter, include_head, include_punctuation, count_images, structure, chapter_count, script_breakdown, count_metadata, encoding, retries, timeout, max_download
+        );
+        if let Some(base) = self.relative_to
+            && !from_cli("relative_to")
+        {
+            cli.relative_to = Some(Some(base));
+        }
+        validate(cli)
+    }
+}
+
+
+/// 检查命令行参数的取值范围与参数之间的依赖、冲突关系，与 `Cli` 上的声明保持一致
+fn validate(cli: &Cli) -> Result<(), clap::Error>
+{
+    let error = |kind: ErrorKind, message: &str| Err(Cli::command().error(kind, message));
+
+    if cli.reading_speed == Some(0) {
+        return error(ErrorKind::ValueValidation, "reading_speed 必须大于 0");
+    }
+    if cli.reading_speed_cjk == Some(0) {
+        return error(ErrorKind::ValueValidation, "reading_speed_cjk 必须大于 0");
+    }
+    if cli.reading_speed_cjk.is_some() && cli.reading_speed.is_none() {
+        return error(ErrorKind::MissingRequiredArgument, "reading_speed_cjk 需要与 reading_speed 一起使用");
+    }
+    if cli.sample == Some(0) {
+        return error(ErrorKind::ValueValidation, "sample 必须大于 0");
+    }
+    if cli.live_total == Some(0) {
+        return error(ErrorKind::ValueValidation, "live_total 必须大于 0");
+    }
+    if cli.seed.is_some() && cli.sample.is_none() {
+        return error(ErrorKind::MissingRequiredArgument, "seed 需要与 sample 一起使用");
+    }
+    if cli.append && cli.output.is_none() {
+        return error(ErrorKind::MissingRequiredArgument, "append 需要与 output 一起使用");
+    }
+    if cli.group_by_author && cli.group_by_dir {
+        return error(ErrorKind::ArgumentConflict, "group_by_author 不能与 group_by_dir 同时使用");
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use clap::FromArgMatches;
+
+    fn apply(config: &str, args: &[&str]) -> Result<Cli, clap::Error>
+    {
+        let matches = Cli::command().try_get_matches_from(["epub-count"].iter().chain(args))?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        let config: Config = toml::from_str(config).unwrap();
+        config.apply(&mut cli, &matches)?;
+        Ok(cli)
+    }
+
+    fn rejected(config: &str, args: &[&str]) -> ErrorKind
+    {
+        apply(config, args).err().expect("配置应当被拒绝").kind()
+    }
+
+    #[test]
+    fn config_values_are_validated_like_arguments()
+    {
+        assert_eq!(rejected("reading_speed = 0", &["a.epub"]), ErrorKind::ValueValidation);
+        assert_eq!(rejected("reading_speed = 250\nreading_speed_cjk = 0", &["a.epub"]), ErrorKind::ValueValidation);
+        assert_eq!(rejected("reading_speed_cjk = 500", &["a.epub"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(rejected("sample = 0", &["a.epub"]), ErrorKind::ValueValidation);
+        assert_eq!(rejected("live_total = 0", &["a.epub"]), ErrorKind::ValueValidation);
+        assert_eq!(rejected("seed = 1", &["a.epub"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(rejected("append = true", &["a.epub"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(rejected("group_by_author = true", &["--group-by-dir", "a.epub"]), ErrorKind::ArgumentConflict);
+
+        assert_eq!(apply("seed = 1", &["--sample", "3", "a.epub"]).unwrap().seed, Some(1));
+        assert!(apply("append = true\noutput = \"out.txt\"", &["a.epub"]).unwrap().append);
+        // 命令行上的合法值覆盖配置文件中的非法值
+        assert_eq!(apply("reading_speed = 0", &["--reading-speed", "300", "a.epub"]).unwrap().reading_speed, Some(300));
+    }
+}
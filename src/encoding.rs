@@ -0,0 +1,103 @@
+//! 内容文档的字符编码识别与解码
+//!
+//! 依次检查 BOM、`<?xml encoding="...">` 声明与 `<meta charset>`，
+//! 都没有时按 UTF-8 解码，无效字节替换为 U+FFFD 而不是报错。
+//! 用 `--encoding` 指定编码时跳过识别，直接按指定的编码解码。
+
+use encoding_rs::{Encoding, UTF_8};
+
+
+/// 只在文档开头的这么多字节内查找编码声明
+const SNIFF_LEN: usize = 1024;
+
+
+/// 将内容文档的原始字节解码为字符串
+pub fn decode(bytes: &[u8]) -> String
+{
+    decode_with(bytes, None)
+}
+
+
+/// 按 `forced` 解码，为 `None` 时先识别文档声明的编码
+///
+/// 指定编码时只去掉与之匹配的 BOM，不再根据 BOM 或声明切换编码。
+pub fn decode_with(bytes: &[u8], forced: Option<&'static Encoding>) -> String
+{
+    if let Some(encoding) = forced {
+        return encoding.decode_with_bom_removal(bytes).0.into_owned();
+    }
+    let encoding = sniff(bytes).unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+
+/// 识别文档声明的编码
+pub fn sniff(bytes: &[u8]) -> Option<&'static Encoding>
+{
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+
+    let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+
+    if head.trim_start().starts_with("<?xml") {
+        let decl_end = head.find("?>").unwrap_or(head.len());
+        if let Some(label) = attribute_value(&head[..decl_end], "encoding") {
+            return Encoding::for_label(label.as_bytes());
+        }
+    }
+
+    let charset = head.find("charset=")?;
+    let label = attribute_value(&head[charset..], "charset")?;
+    Encoding::for_label(label.as_bytes())
+}
+
+
+/// 取出 `name="value"`、`name='value'` 或 `name=value` 形式的值
+fn attribute_value<'a>(s: &'a str, name: &str) -> Option<&'a str>
+{
+    let start = s.find(name)? + name.len();
+    let rest = s[start..].trim_start().strip_prefix('=')?.trim_start();
+    match rest.chars().next()? {
+        q @ ('"' | '\'') => {
+            let rest = &rest[1..];
+            rest.find(q).map(|end| &rest[..end])
+        }
+        _ => {
+            let end = rest.find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '>' | ';' | '/'))
+                .unwrap_or(rest.len());
+            Some(&rest[..end])
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use encoding_rs::{GBK, SHIFT_JIS};
+
+    #[test]
+    fn sniff_declared_encodings()
+    {
+        assert_eq!(sniff(br#"<?xml version="1.0" encoding="GBK"?><html/>"#), Some(GBK));
+        assert_eq!(sniff(b"<html><head><meta charset=shift_jis></head></html>"), Some(SHIFT_JIS));
+        assert_eq!(sniff(br#"<meta http-equiv="Content-Type" content="text/html; charset=gb2312"/>"#), Some(GBK));
+        assert_eq!(sniff(b"\xEF\xBB\xBF<html/>"), Some(UTF_8));
+        assert_eq!(sniff(b"<html><body>no declaration</body></html>"), None);
+    }
+
+    #[test]
+    fn decode_gbk_document()
+    {
+        let (body, _, _) = GBK.encode("<p>中文内容</p>");
+        let mut bytes = br#"<?xml version="1.0" encoding="gbk"?>"#.to_vec();
+        bytes.extend_from_slice(&body);
+        assert!(decode(&bytes).ends_with("<p>中文内容</p>"));
+        // 没有声明时按 UTF-8 解码，无效字节替换而不是报错
+        assert!(decode(&body).contains('\u{FFFD}'));
+    }
+}
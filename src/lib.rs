@@ -0,0 +1,1618 @@
+//! EPUB 字数统计的核心逻辑
+//!
+//! 命令行工具只是对这里的函数做了一层包装，也可以作为依赖直接调用。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Cursor, ErrorKind, Read, Seek};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use jieba_rs::Jieba;
+use unicode_normalization::char::is_combining_mark;
+use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
+use log::{debug, warn};
+use serde::Serialize;
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::{ZipArchive, ZipReadOptions};
+use regex::Regex;
+use encoding_rs::Encoding;
+use scraper::{ElementRef, Html, Selector};
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+pub mod archive;
+pub mod encoding;
+pub mod opf;
+pub mod walk;
+
+pub use walk::{get_all_books_walkdir, get_all_epub_walkdir, WalkOptions};
+
+
+/// 可读且可定位的数据源，`open_file` 的返回类型
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+
+/// 统计时的可调参数
+#[derive(Clone)]
+pub struct CountOptions
+{
+    /// 单个内容文档解压后的最大字节数，超过时跳过该文档
+    pub max_entry_size: u64,
+    /// 一个 EPUB 中所有内容文档解压后的总字节上限，超过时整个文件统计失败
+    pub max_total_size: u64,
+    /// 是否尝试用 mmap 读取文件；网络文件系统上文件被截断时 mmap 可能触发 SIGBUS
+    pub mmap: bool,
+    /// 是否同时统计词频（用于词汇量等统计），会增加内存占用
+    pub collect_frequencies: bool,
+    /// 是否额外用 jieba 分词统计中文词数，速度较慢
+    pub segment: bool,
+    /// `mimetype` 缺失或不正确时报错，而不是只打印警告后继续统计
+    pub strict: bool,
+    /// 分词的最小单位
+    pub unit: CountUnit,
+    /// 计数方式
+    pub mode: CountMode,
+    /// 保留注音（`<rt>`/`<rp>`）的内容，默认只统计被注音的正文
+    pub keep_ruby: bool,
+    /// 只统计 zip 内路径匹配该正则的内容文档，`None` 表示全部统计
+    pub chapter_filter: Option<Regex>,
+    /// 打开文件遇到暂时性错误时的重试次数
+    pub retries: u32,
+    /// 是否同时统计段落数与句子数
+    pub structure: bool,
+    /// 强制使用的内容文档编码，`None` 表示自动识别
+    pub encoding: Option<&'static Encoding>,
+    /// 只统计匹配该 CSS 选择器的元素中的文本，`None` 表示统计整个文档
+    pub content_selector: Option<Selector>,
+    /// 跳过 OPF guide 与 EPUB3 landmarks 中标记为封面、版权页、目录等的文档
+    pub exclude_front_matter: bool,
+    /// 同时统计 `<head>` 中的文本（如 `<title>`），默认只统计 `<body>`
+    pub include_head: bool,
+    /// 词以外的每个标点、符号都计为一个字，见 [`Tokens`]
+    pub include_punctuation: bool,
+    /// 只统计 spine 中第 `start..=end` 个内容文档（从 1 开始，不含导航文档）
+    pub chapter_range: Option<(usize, usize)>,
+    /// 是否按文字种类（CJK、拉丁字母、数字、其他）分别统计字符数
+    pub script_breakdown: bool,
+    /// 解密 zip 加密（ZipCrypto 或 AES）条目的密码，与 DRM 无关
+    pub password: Option<String>
+}
+
+impl Default for CountOptions
+{
+    fn default() -> Self
+    {
+        CountOptions {
+            max_entry_size: 64 * 1024 * 1024,
+            max_total_size: 1024 * 1024 * 1024,
+            mmap: true,
+            collect_frequencies: false,
+            segment: false,
+            strict: false,
+            unit: CountUnit::Chars,
+            mode: CountMode::Chars,
+            keep_ruby: false,
+            chapter_filter: None,
+            retries: 3,
+            structure: false,
+            encoding: None,
+            content_selector: None,
+            exclude_front_matter: false,
+            include_head: false,
+            include_punctuation: false,
+            chapter_range: None,
+            script_breakdown: false,
+            password: None
+        }
+    }
+}
+
+
+impl CountOptions
+{
+    /// 内容文档是否满足 `chapter_filter`
+    fn is_selected(&self, name: &str) -> bool
+    {
+        self.chapter_filter.as_ref().is_none_or(|re| re.is_match(name))
+    }
+
+    fn password(&self) -> Option<&[u8]>
+    {
+        self.password.as_deref().map(str::as_bytes)
+    }
+}
+
+
+/// 单个文件的统计结果
+#[derive(Serialize, Default)]
+pub struct FileWordCount
+{
+    /// 在输入中的原始顺序，仅用于排序，不参与序列化
+    #[serde(skip)]
+    pub index: usize,
+    pub filename: String,
+    /// 文件的完整路径，仅在调用方需要时填充
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub word_count: u64,
+    /// OPF 中的书名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// OPF 中的作者
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub creators: Vec<String>,
+    /// 每个内容文档的字数，仅在需要分章统计时填充
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chapters: Option<Vec<ChapterWordCount>>,
+    /// 整本书的词频，仅在 `collect_frequencies` 时填充
+    #[serde(skip)]
+    pub frequencies: Option<Frequencies>,
+    /// jieba 分词后的词数，仅在 `segment` 时填充
+    #[serde(skip)]
+    pub segmented_words: Option<u64>,
+    /// 统计耗时，仅在调用方需要计时时填充
+    #[serde(skip)]
+    pub elapsed: Option<Duration>,
+    /// 书籍的语言，来自 `<dc:language>` 或统计识别，仅在需要时填充
+    #[serde(skip)]
+    pub language: Option<String>,
+    /// 图片资源的数量，仅在需要时填充
+    #[serde(skip)]
+    pub images: Option<u64>,
+    /// 段落数，仅在 `structure` 时填充
+    #[serde(skip)]
+    pub paragraphs: Option<u64>,
+    /// 句子数，仅在 `structure` 时填充
+    #[serde(skip)]
+    pub sentences: Option<u64>,
+    /// 内容文档（章节）数，仅在需要时填充
+    #[serde(skip)]
+    pub chapter_count: Option<u64>,
+    /// `--count-metadata` 时 `<dc:description>` 的字数，已计入 `word_count`
+    #[serde(skip)]
+    pub metadata_words: Option<u64>,
+    /// 各类文字的字符数，仅在需要时填充
+    #[serde(skip)]
+    pub scripts: Option<ScriptCounts>,
+    /// `.cbz` 的页数，此时 `word_count` 为 0
+    #[serde(skip)]
+    pub pages: Option<u64>
+}
+
+
+/// 词 -> 出现次数，拉丁字母统一为小写
+pub type Frequencies = HashMap<String, u64>;
+
+
+/// 单个内容文档（章节）的统计结果
+#[derive(Serialize)]
+pub struct ChapterWordCount
+{
+    /// 内容文档在 zip 中的路径
+    pub name: String,
+    pub word_count: u64,
+    /// 该文档的词频，仅在 `collect_frequencies` 时填充
+    #[serde(skip)]
+    pub frequencies: Option<Frequencies>,
+    /// jieba 分词后的词数，仅在 `segment` 时填充
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segmented_words: Option<u64>,
+    /// 段落数，仅在 `structure` 时填充
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paragraphs: Option<u64>,
+    /// 句子数，仅在 `structure` 时填充
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sentences: Option<u64>,
+    /// 各类文字的字符数，仅在 `script_breakdown` 时填充
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scripts: Option<ScriptCounts>
+}
+
+
+/// 按文字种类统计的字符数，空白不计
+#[derive(Serialize, Default, Clone, Copy, Debug)]
+pub struct ScriptCounts
+{
+    /// 汉字、假名与谚文（不含全角标点）
+    pub cjk: u64,
+    /// 拉丁字母，包括带附加符号的字母与全角字母
+    pub latin: u64,
+    /// 各种数字
+    pub digits: u64,
+    /// 其余字符，主要是标点、符号与其他文字
+    pub other: u64
+}
+
+impl ScriptCounts
+{
+    pub fn add(&mut self, other: &ScriptCounts)
+    {
+        self.cjk += other.cjk;
+        self.latin += other.latin;
+        self.digits += other.digits;
+        self.other += other.other;
+    }
+}
+
+
+/// [`get_epub_word_count`] 的结果
+#[derive(Serialize, Debug)]
+pub struct WordCountReport
+{
+    pub total: u64,
+    /// 每个内容文档在 zip 中的路径与字数，按阅读顺序排列
+    pub per_chapter: Vec<(String, u64)>,
+    /// OPF 中的 `<dc:title>`
+    pub title: Option<String>,
+    /// OPF 中的 `<dc:language>`，不做统计识别
+    pub language: Option<String>
+}
+
+
+/// EPUB 的内容被 DRM 加密，无法统计
+///
+/// 作为 `anyhow::Error` 返回，可以用 `downcast_ref::<Encrypted>()` 区分。
+#[derive(Debug)]
+pub struct Encrypted;
+
+impl fmt::Display for Encrypted
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "内容已加密（DRM），无法统计")
+    }
+}
+
+impl std::error::Error for Encrypted {}
+
+
+/// 从 EPUB 中读取的一个内容文档
+pub struct ContentDocument
+{
+    /// 内容文档在 zip 中的路径
+    pub name: String,
+    pub content: String
+}
+
+
+/// 判断路径是否为纯文本（`.txt`）文件
+pub fn is_txt<P: AsRef<Path>>(path: P) -> bool
+{
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"))
+}
+
+
+/// 是否为 `.cbz` 漫画压缩包，这类文件统计页数（图片数）而不是字数
+pub fn is_cbz<P: AsRef<Path>>(path: P) -> bool
+{
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cbz"))
+}
+
+
+/// 判断字符是否按“一字一词”计数：中日韩表意文字、假名、谚文以及全角标点
+pub fn is_cjk(c: char) -> bool
+{
+    matches!(c as u32,
+        0x3001..=0x303F     // CJK 符号和标点（不含全角空格 U+3000）
+        | 0x3040..=0x30FF   // 平假名、片假名
+        | 0x3400..=0x4DBF   // CJK 扩展 A
+        | 0x4E00..=0x9FFF   // CJK 基本区
+        | 0xAC00..=0xD7AF   // 谚文音节
+        | 0xF900..=0xFAFF   // CJK 兼容表意文字
+        | 0xFF01..=0xFFEF   // 全角 ASCII、半角片假名等
+        | 0x20000..=0x3134F // CJK 扩展 B-G
+    )
+}
+
+
+/// 夹在字母/数字之间时不拆开词的标点，如 `don't`、`e-mail`、`3.14`
+const WORD_JOINERS: [char; 6] = ['\'', '\u{2019}', '-', '\u{2010}', '.', '_'];
+
+
+/// 组合附加符号（Mn/Mc/Me）与零宽连接符属于前一个字符，按字符分词时也不把词拆开
+///
+/// 例如 NFD 形式的 `été` 是 `e` + U+0301 + `t` + `e` + U+0301。
+fn is_attached(c: char) -> bool
+{
+    c == '\u{200D}' || is_combining_mark(c)
+}
+
+
+/// 分词的最小单位
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum CountUnit
+{
+    /// 按 Unicode 标量值（`char`），速度最快
+    #[default]
+    Chars,
+    /// 按扩展字素簇，组合附加符号、变体选择符不会把词拆开
+    Graphemes
+}
+
+
+/// 计数方式
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum CountMode
+{
+    /// CJK 按字、其余按词（默认）
+    #[default]
+    Chars,
+    /// 按空白分隔的词，连续的 CJK 文本只算一个词
+    Words,
+    /// 提取出的文本的 UTF-8 字节数，连续空白按一个空格计算
+    Bytes
+}
+
+
+/// 文本分词器
+///
+/// 每个 CJK 字符单独成为一个词；其余连续的字母/数字组成一个词，
+/// 夹在词内部的 `WORD_JOINERS`（如 `don't`、`e-mail`）与组合附加符号不会把词拆开。
+/// 按字素簇分词时以簇的第一个字符判断类别。
+///
+/// 空白（包括全角空格 U+3000 与 `&nbsp;`）只用来分隔，从不计数。
+/// 默认只有 [`is_cjk`] 范围内的全角标点（如 `，。！「」`）各计一个字，
+/// 半角标点以及通用标点区的 `“”‘’……——` 不计；`include_punctuation` 时
+/// 词以外的所有标点与符号都各计一个字。
+pub struct Tokens<'a>
+{
+    text: &'a str,
+    pos: usize,
+    unit: CountUnit,
+    punctuation: bool
+}
+
+impl<'a> Tokens<'a>
+{
+    pub fn new(text: &'a str) -> Self
+    {
+        Tokens::with_unit(text, CountUnit::Chars)
+    }
+
+    pub fn with_unit(text: &'a str, unit: CountUnit) -> Self
+    {
+        Tokens { text, pos: 0, unit, punctuation: false }
+    }
+
+    /// 按 `options` 中的分词单位与标点设置分词
+    pub fn with_options(text: &'a str, options: &CountOptions) -> Self
+    {
+        Tokens { text, pos: 0, unit: options.unit, punctuation: options.include_punctuation }
+    }
+}
+
+
+/// 按 `CountUnit` 切分出的单位及其字节偏移
+enum Units<'a>
+{
+    Chars(&'a str, std::str::CharIndices<'a>),
+    Graphemes(GraphemeIndices<'a>)
+}
+
+impl<'a> Iterator for Units<'a>
+{
+    type Item = (usize, char, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        match self {
+            Units::Chars(s, chars) => chars.next().map(|(i, c)| (i, c, &s[i..i + c.len_utf8()])),
+            Units::Graphemes(graphemes) => graphemes.next()
+                .map(|(i, g)| (i, g.chars().next().unwrap_or_default(), g))
+        }
+    }
+}
+
+
+impl<'a> Iterator for Tokens<'a>
+{
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str>
+    {
+        let rest = &self.text[self.pos..];
+        let mut units = match self.unit {
+            CountUnit::Chars => Units::Chars(rest, rest.char_indices()),
+            CountUnit::Graphemes => Units::Graphemes(rest.grapheme_indices(true))
+        }.peekable();
+
+        let start = loop {
+            let (i, c, unit) = units.next()?;
+            if c.is_whitespace() || is_attached(c) {
+                continue;
+            }
+            if is_cjk(c) {
+                self.pos += i + unit.len();
+                return Some(&rest[i..i + unit.len()]);
+            }
+            if c.is_alphanumeric() {
+                break i;
+            }
+            if self.punctuation && !c.is_control() {
+                self.pos += i + unit.len();
+                return Some(&rest[i..i + unit.len()]);
+            }
+        };
+
+        let mut end = rest.len();
+        while let Some((i, c, _)) = units.next() {
+            if (c.is_alphanumeric() && !is_cjk(c)) || is_attached(c) {
+                continue;
+            }
+            let joins = WORD_JOINERS.contains(&c)
+                && units.peek().is_some_and(|&(_, n, _)| n.is_alphanumeric() && !is_cjk(n));
+            if !joins {
+                end = i;
+                break;
+            }
+        }
+
+        self.pos += end;
+        Some(&rest[start..end])
+    }
+}
+
+
+/// 提取文本时整棵子树都跳过的元素，`<head>` 在 `include_head` 时保留
+///
+/// 解析器会把 `<body>` 外误放的正文移入 `<body>`，跳过 `<head>` 后统计的就只是正文。
+const SKIPPED_ELEMENTS: [&str; 3] = ["head", "script", "style"];
+
+/// 注音标记，默认跳过以免振假名与正文重复计数
+const RUBY_ELEMENTS: [&str; 2] = ["rt", "rp"];
+
+/// 块级元素，提取文本时在其前后插入空白，避免相邻段落的词粘在一起
+///
+/// 其余元素按行内处理，直接拼接子节点文本。Kobo 的 kepub 会用
+/// `<span class="koboSpan">` 把句子甚至单词切成多段，拼接后与原书计数一致。
+const BLOCK_ELEMENTS: [&str; 32] = [
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption",
+    "figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "main", "nav",
+    "ol", "p", "pre", "section", "table", "td", "th", "tr", "ul"
+];
+
+
+/// 提取 `element` 下的正文追加到 `out`，`paragraphs` 累加直接包含文本的块级元素个数
+fn collect_text(element: ElementRef, out: &mut String, paragraphs: &mut u64, options: &CountOptions)
+{
+    let mut has_text = false;
+    for child in element.children() {
+        if let Some(el) = ElementRef::wrap(child) {
+            let name = el.value().name();
+            let skipped = match name {
+                "head" => !options.include_head,
+                _ => SKIPPED_ELEMENTS.contains(&name)
+            };
+            if skipped || (!options.keep_ruby && RUBY_ELEMENTS.contains(&name)) {
+                continue;
+            }
+            let block = BLOCK_ELEMENTS.contains(&name);
+            if block {
+                out.push('\n');
+            }
+            collect_text(el, out, paragraphs, options);
+            if block {
+                out.push('\n');
+            }
+        } else if let Some(text) = child.value().as_text() {
+            has_text |= !text.trim().is_empty();
+            out.push_str(text);
+        }
+    }
+    if has_text && BLOCK_ELEMENTS.contains(&element.value().name()) {
+        *paragraphs += 1;
+    }
+}
+
+
+/// 提取 (X)HTML 文档中参与计数的正文文本
+///
+/// 解析器已经处理了常规实体，但 CDATA、重复转义或畸形标记中的实体（如 `&#x4e2d;`）
+/// 会原样留在文本里，这里再解码一次。`&nbsp;` 与全角空格 U+3000 在分词时按空白处理。
+/// 注音（`<rt>`/`<rp>`）默认不计入。
+pub fn html_text(string: &str) -> String
+{
+    html_text_with(string, &CountOptions::default())
+}
+
+
+/// 按 `options` 提取 (X)HTML 文档的正文文本
+pub fn html_text_with(string: &str, options: &CountOptions) -> String
+{
+    html_text_paragraphs(string, options).0
+}
+
+
+/// 提取 (X)HTML 文档的正文文本，同时返回段落数（直接包含文本的块级元素个数）
+pub fn html_text_paragraphs(string: &str, options: &CountOptions) -> (String, u64)
+{
+    let document = Html::parse_document(string);
+    let mut text = String::new();
+    let mut paragraphs = 0;
+    match &options.content_selector {
+        Some(selector) => {
+            // 嵌套的匹配元素已经包含在外层匹配中，跳过以免重复计数
+            let outermost = document.select(selector).filter(|el| {
+                !el.ancestors().filter_map(ElementRef::wrap).any(|a| selector.matches(&a))
+            });
+            for el in outermost {
+                text.push('\n');
+                collect_text(el, &mut text, &mut paragraphs, options);
+            }
+        }
+        None => collect_text(document.root_element(), &mut text, &mut paragraphs, options)
+    }
+    if text.contains('&') {
+        text = html_escape::decode_html_entities(&text).into_owned();
+    }
+    (text, paragraphs)
+}
+
+
+/// 句末标点
+const SENTENCE_TERMINATORS: [char; 6] = ['。', '！', '？', '.', '!', '?'];
+
+
+/// 统计句子数：按句末标点与换行切分，含有文字的片段记为一句
+///
+/// 连续的句末标点（如 `？！`、`...`）只切分一次；没有句末标点的标题、段落单独成句。
+pub fn sentence_count(text: &str) -> u64
+{
+    text.split(|c| SENTENCE_TERMINATORS.contains(&c) || c == '\n')
+        .filter(|s| s.chars().any(char::is_alphanumeric))
+        .count() as u64
+}
+
+
+/// 是否为拉丁字母：基本拉丁、拉丁补充与扩展区中的字母以及全角拉丁字母
+fn is_latin(c: char) -> bool
+{
+    c.is_alphabetic()
+        && matches!(c as u32,
+            0x0041..=0x024F     // 基本拉丁、拉丁补充、扩展 A/B
+            | 0x1E00..=0x1EFF   // 拉丁扩展附加
+            | 0xFF21..=0xFF3A   // 全角大写字母
+            | 0xFF41..=0xFF5A   // 全角小写字母
+        )
+}
+
+
+/// 按文字种类统计 `text` 中的字符数
+pub fn script_counts(text: &str) -> ScriptCounts
+{
+    let mut counts = ScriptCounts::default();
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        // 全角拉丁字母也落在 is_cjk 的范围内，必须先判断
+        if is_latin(c) {
+            counts.latin += 1;
+        } else if is_cjk(c) && c.is_alphabetic() {
+            counts.cjk += 1;
+        } else if c.is_numeric() {
+            counts.digits += 1;
+        } else {
+            counts.other += 1;
+        }
+    }
+    counts
+}
+
+
+/// 统计纯文本的段落数，每个非空行记为一段
+pub fn text_paragraph_count(text: &str) -> u64
+{
+    text.lines().filter(|line| !line.trim().is_empty()).count() as u64
+}
+
+
+/// 把一段纯文本当作单个文档统计，`structure` 时段落按非空行计
+pub fn count_plain_text(name: String, text: &str, options: &CountOptions) -> ChapterWordCount
+{
+    let mut chapter = count_text(name, text, options);
+    if options.structure {
+        chapter.paragraphs = Some(text_paragraph_count(text));
+    }
+    chapter
+}
+
+
+/// 统计一个 (X)HTML 文档正文的字数
+///
+/// 全角空格与各类标点的计数规则见 [`Tokens`]。
+pub fn html_word_count(string: &str) -> u64
+{
+    text_word_count(&html_text(string))
+}
+
+
+/// 按 `options.mode` 统计一个 (X)HTML 文档的正文
+pub fn html_count(string: &str, options: &CountOptions) -> u64
+{
+    text_count(&html_text_with(string, options), options)
+}
+
+
+/// 用 jieba 分词统计词数，标点与空白不计入
+pub fn segmented_word_count(text: &str) -> u64
+{
+    static JIEBA: OnceLock<Jieba> = OnceLock::new();
+    JIEBA.get_or_init(Jieba::new)
+        .cut(text, true)
+        .iter()
+        .filter(|token| token.word.chars().any(char::is_alphanumeric))
+        .count() as u64
+}
+
+
+/// 统计一段已提取的文本，需要时一并统计词频与分词词数
+pub fn count_text(name: String, text: &str, options: &CountOptions) -> ChapterWordCount
+{
+    let segmented_words = options.segment.then(|| segmented_word_count(text));
+    let sentences = options.structure.then(|| sentence_count(text));
+    let scripts = options.script_breakdown.then(|| script_counts(text));
+
+    if !options.collect_frequencies {
+        return ChapterWordCount {
+            name,
+            word_count: text_count(text, options),
+            frequencies: None,
+            segmented_words,
+            paragraphs: None,
+            sentences,
+            scripts
+        };
+    }
+
+    let mut frequencies = Frequencies::new();
+    let mut tokens = 0;
+    for token in Tokens::with_options(text, options) {
+        tokens += 1;
+        *frequencies.entry(token.to_lowercase()).or_insert(0) += 1;
+    }
+    let word_count = if options.mode == CountMode::Chars { tokens } else { text_count(text, options) };
+    ChapterWordCount {
+        name,
+        word_count,
+        frequencies: Some(frequencies),
+        segmented_words,
+        paragraphs: None,
+        sentences,
+        scripts
+    }
+}
+
+
+/// 把 `other` 的词频累加到 `into`
+pub fn merge_frequencies(into: &mut Frequencies, other: Frequencies)
+{
+    for (word, n) in other {
+        *into.entry(word).or_insert(0) += n;
+    }
+}
+
+
+/// 统计纯文本的字数
+pub fn text_word_count(text: &str) -> u64
+{
+    Tokens::new(text).count() as u64
+}
+
+
+/// 按 `options.mode` 统计一段已提取的文本
+pub fn text_count(text: &str, options: &CountOptions) -> u64
+{
+    match options.mode {
+        CountMode::Chars => Tokens::with_options(text, options).count() as u64,
+        CountMode::Words => text.split_whitespace()
+            .filter(|w| options.include_punctuation || w.chars().any(char::is_alphanumeric))
+            .count() as u64,
+        CountMode::Bytes => {
+            let (bytes, words) = text.split_whitespace().fold((0usize, 0usize), |(b, n), w| (b + w.len(), n + 1));
+            (bytes + words.saturating_sub(1)) as u64
+        }
+    }
+}
+
+
+/// 读取一个内容文档并按其声明的编码解码，解压后超过 `limit` 字节时报错
+fn read_content<R: Read>(file: R, name: &str, size: u64, limit: u64, forced: Option<&'static Encoding>) -> Result<String>
+{
+    if size > limit {
+        bail!("声明的大小 {} 字节超过上限 {} 字节", size, limit);
+    }
+    let mut bytes = Vec::with_capacity(size as usize);
+    // 声明的大小可能是伪造的，实际读取时同样限制长度
+    file.take(limit + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        bail!("解压后超过上限 {} 字节", limit);
+    }
+    if let Some(forced) = forced {
+        debug!("{}：{} 字节，按指定的编码 {} 解码", name, bytes.len(), forced.name());
+    } else if log::log_enabled!(log::Level::Debug) {
+        let detected = encoding::sniff(&bytes).map_or("未声明，按 UTF-8", |e| e.name());
+        debug!("{}：{} 字节，编码 {}", name, bytes.len(), detected);
+    }
+    Ok(encoding::decode_with(&bytes, forced))
+}
+
+
+/// 读取 zip 中的一个条目，`forced` 为 `None` 时自动识别编码
+fn read_zip_entry<W: Read + Seek>(
+    zip: &mut ZipArchive<W>,
+    name: &str,
+    limit: u64,
+    forced: Option<&'static Encoding>,
+    password: Option<&[u8]>
+) -> Result<String>
+{
+    let index = zip.index_for_name(name).with_context(|| format!("zip中没有文件 {}", name))?;
+    let file = zip_entry(zip, index, password).with_context(|| format!("读取zip内文件 {} 时出现错误", name))?;
+    let size = file.size();
+    read_content(file, name, size, limit, forced).with_context(|| format!("读取 {} 时出现错误", name))
+}
+
+
+/// 打开 zip 中的第 `index` 个条目，条目被 zip 加密时用 `password` 解密
+fn zip_entry<'a, W: Read + Seek>(zip: &'a mut ZipArchive<W>, index: usize, password: Option<&[u8]>) -> Result<ZipFile<'a, W>>
+{
+    zip.by_index_with_options(index, ZipReadOptions::new().password(password)).map_err(|e| match e {
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => anyhow!("条目已加密，需要用 --password 提供密码"),
+        ZipError::InvalidPassword => anyhow!("密码错误"),
+        e => e.into()
+    })
+}
+
+
+/// 通过 `container.xml` 找到并解析 OPF，EPUB 结构不完整时返回 `None`
+fn read_package<W: Read + Seek>(zip: &mut ZipArchive<W>, options: &CountOptions) -> Option<opf::Package>
+{
+    let container = read_zip_entry(zip, opf::CONTAINER_PATH, options.max_entry_size, None, options.password()).ok()?;
+    let opf_path = opf::find_rootfile(&container).ok()?;
+    let opf = read_zip_entry(zip, &opf_path, options.max_entry_size, None, options.password()).ok()?;
+    opf::parse_package(&opf, &opf_path).ok()
+}
+
+
+/// zip 文件至少包含一个 22 字节的中央目录结束记录
+const MIN_ZIP_SIZE: u64 = 22;
+
+
+/// 打开 zip，空文件或小于最小 zip 大小的文件（多为下载中断）单独报错
+fn open_zip<W: Read + Seek>(mut file: W) -> Result<ZipArchive<W>>
+{
+    let len = file.seek(std::io::SeekFrom::End(0)).context("读取zip文件时出现错误")?;
+    if len < MIN_ZIP_SIZE {
+        bail!("文件为空或已截断（{} 字节）", len);
+    }
+    file.rewind().context("读取zip文件时出现错误")?;
+    ZipArchive::new(file).context("读取zip文件时出现错误，文件可能已截断")
+}
+
+
+/// 读取 EPUB 的 OPF 元数据，找不到 OPF 时返回 `None`
+pub fn zip_metadata_read<W: Read + Seek>(file: W) -> Result<Option<opf::Metadata>>
+{
+    zip_metadata_read_with(file, &CountOptions::default())
+}
+
+
+/// 同 [`zip_metadata_read`]，`options.password` 用于读取被 zip 加密的 OPF
+pub fn zip_metadata_read_with<W: Read + Seek>(file: W, options: &CountOptions) -> Result<Option<opf::Metadata>>
+{
+    let mut zip = open_zip(file)?;
+    Ok(read_package(&mut zip, options).map(|p| p.metadata))
+}
+
+
+/// 视为图片的资源扩展名
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "svg", "webp"];
+
+
+/// 统计 EPUB 中图片资源的数量
+pub fn zip_image_count<W: Read + Seek>(file: W) -> Result<u64>
+{
+    let zip = open_zip(file)?;
+    let count = zip.file_names()
+        .filter(|name| {
+            name.rsplit_once('.')
+                .is_some_and(|(_, ext)| IMAGE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+        })
+        .count();
+    Ok(count as u64)
+}
+
+
+/// 统计 EPUB 文件中图片资源的数量，也用作 `.cbz` 漫画的页数
+pub fn get_epub_image_count<P: AsRef<Path>>(path: P, options: &CountOptions) -> Result<u64>
+{
+    zip_image_count(open_file(path, options)?)
+}
+
+
+/// 识别语言时最多使用的正文字节数
+const LANGUAGE_SAMPLE: usize = 64 * 1024;
+
+
+/// whatlang 的 ISO 639-3 代码与对应的 ISO 639-1 代码
+///
+/// BCP 47 在有两字母代码时必须使用两字母代码，换算后才能与 OPF 中的 `<dc:language>` 比较。
+const ISO_639_1: [(&str, &str); 70] = [
+    ("epo", "eo"), ("eng", "en"), ("rus", "ru"), ("cmn", "zh"), ("spa", "es"), ("por", "pt"), ("ita", "it"),
+    ("ben", "bn"), ("fra", "fr"), ("deu", "de"), ("ukr", "uk"), ("kat", "ka"), ("ara", "ar"), ("hin", "hi"),
+    ("jpn", "ja"), ("heb", "he"), ("yid", "yi"), ("pol", "pl"), ("amh", "am"), ("jav", "jv"), ("kor", "ko"),
+    ("nob", "nb"), ("dan", "da"), ("swe", "sv"), ("fin", "fi"), ("tur", "tr"), ("nld", "nl"), ("hun", "hu"),
+    ("ces", "cs"), ("ell", "el"), ("bul", "bg"), ("bel", "be"), ("mar", "mr"), ("kan", "kn"), ("ron", "ro"),
+    ("slv", "sl"), ("hrv", "hr"), ("srp", "sr"), ("mkd", "mk"), ("lit", "lt"), ("lav", "lv"), ("est", "et"),
+    ("tam", "ta"), ("vie", "vi"), ("urd", "ur"), ("tha", "th"), ("guj", "gu"), ("uzb", "uz"), ("pan", "pa"),
+    ("aze", "az"), ("ind", "id"), ("tel", "te"), ("pes", "fa"), ("mal", "ml"), ("ori", "or"), ("mya", "my"),
+    ("nep", "ne"), ("sin", "si"), ("khm", "km"), ("tuk", "tk"), ("aka", "ak"), ("zul", "zu"), ("sna", "sn"),
+    ("afr", "af"), ("lat", "la"), ("slk", "sk"), ("cat", "ca"), ("tgl", "tl"), ("hye", "hy"), ("cym", "cy")
+];
+
+
+/// 用统计方法识别文本的语言，返回 BCP 47 主语言子标签（如 `zh`、`en`），不可靠时返回 `None`
+pub fn detect_language(text: &str) -> Option<String>
+{
+    let end = text.char_indices()
+        .map(|(i, _)| i)
+        .find(|&i| i >= LANGUAGE_SAMPLE)
+        .unwrap_or(text.len());
+    whatlang::detect(&text[..end])
+        .filter(|info| info.is_reliable())
+        .map(|info| {
+            let code = info.lang().code();
+            ISO_639_1.iter().find(|(iso3, _)| *iso3 == code).map_or(code, |(_, iso1)| iso1).to_string()
+        })
+}
+
+
+/// 读取 EPUB 开头的一部分正文，用于识别语言
+///
+/// `source` 用作警告信息的前缀，通常是文件名。
+pub fn zip_text_sample<W: Read + Seek>(file: W, source: &str, options: &CountOptions) -> Result<String>
+{
+    let mut zip = open_zip(file)?;
+    let mut sample = String::new();
+    read_archive(&mut zip, source, options, |doc| {
+        sample.push_str(&html_text_with(&doc.content, options));
+        if sample.len() >= LANGUAGE_SAMPLE { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    })?;
+    Ok(sample)
+}
+
+
+/// 读取 EPUB 文件开头的一部分正文，用于识别语言
+pub fn get_epub_text_sample<P: AsRef<Path>>(path: P, options: &CountOptions) -> Result<String>
+{
+    let path = path.as_ref();
+    let source = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    zip_text_sample(open_file(path, options)?, &source, options)
+}
+
+
+/// 读取一个 EPUB 文件的 OPF 元数据
+pub fn get_epub_metadata<P: AsRef<Path>>(path: P, options: &CountOptions) -> Result<Option<opf::Metadata>>
+{
+    zip_metadata_read_with(open_file(path, options)?, options)
+}
+
+
+/// 检查 zip 的第一个条目是否为未压缩的 `mimetype`，内容为 `application/epub+zip`
+fn check_mimetype<W: Read + Seek>(zip: &mut ZipArchive<W>) -> Result<()>
+{
+    let mut first = zip.by_index(0).context("zip文件为空")?;
+    if first.name() != "mimetype" {
+        bail!("第一个条目不是 mimetype");
+    }
+    if first.compression() != zip::CompressionMethod::Stored {
+        bail!("mimetype 被压缩存储");
+    }
+    let mut content = String::new();
+    first.by_ref().take(64).read_to_string(&mut content).context("读取mimetype时出现错误")?;
+    if content.trim() != "application/epub+zip" {
+        bail!("mimetype 的内容为 {:?}，不是 application/epub+zip", content.trim());
+    }
+    Ok(())
+}
+
+
+/// 累计一个 EPUB 中已读取的内容大小，超过 `max_total_size` 时报错
+struct ContentBudget
+{
+    used: u64,
+    limit: u64
+}
+
+impl ContentBudget
+{
+    fn add(&mut self, bytes: usize) -> Result<()>
+    {
+        self.used += bytes as u64;
+        if self.used > self.limit {
+            bail!("内容文档解压后的总大小超过上限 {} 字节", self.limit);
+        }
+        Ok(())
+    }
+}
+
+
+/// 读取 EPUB 中所有需要计数的内容文档
+///
+/// 存在 OPF 时按 spine 顺序读取，否则退回到遍历 zip 中的全部 `.xhtml`/`.html`。
+/// 超过 `max_entry_size` 的文档会被跳过，总大小超过 `max_total_size` 时返回错误。
+/// `encryption.xml` 声明了字体混淆以外的加密时返回 [`Encrypted`]。
+/// `mimetype` 不符合规范时打印警告，`options.strict` 时返回错误。
+pub fn zip_xhtml_read<W: Read + Seek>(file: W, options: &CountOptions) -> Result<Vec<ContentDocument>> {
+    let mut zip = open_zip(file)?;
+    let mut results = Vec::new();
+    read_archive(&mut zip, "", options, |doc| {
+        results.push(doc);
+        ControlFlow::Continue(())
+    })?;
+    Ok(results)
+}
+
+
+/// `zip_xhtml_read` 的实现，`source` 非空时作为警告的前缀，用来指出是哪个文件
+///
+/// 每读出一个文档就交给 `each` 处理，调用方不必同时持有全部文档；`each` 返回
+/// `Break` 时不再读取后面的文档。
+fn read_archive<W, F>(zip: &mut ZipArchive<W>, source: &str, options: &CountOptions, mut each: F) -> Result<()>
+where
+    W: Read + Seek,
+    F: FnMut(ContentDocument) -> ControlFlow<()>
+{
+    let source = if source.is_empty() { String::new() } else { format!("{}：", source) };
+
+    if let Err(e) = check_mimetype(zip) {
+        if options.strict {
+            return Err(e.context("不是有效的EPUB文件"));
+        }
+        warn!("{}可能不是有效的EPUB文件：{:#}", source, e);
+    }
+
+    if zip.index_for_name(opf::ENCRYPTION_PATH).is_some() {
+        let encryption = read_zip_entry(zip, opf::ENCRYPTION_PATH, options.max_entry_size, None, options.password())?;
+        let encrypted = opf::encrypted_resources(&encryption)?;
+        if !encrypted.is_empty() {
+            debug!("encryption.xml 中有 {} 个加密资源", encrypted.len());
+            return Err(Encrypted.into());
+        }
+    }
+
+    let package = read_package(zip, options);
+    let mut budget = ContentBudget { used: 0, limit: options.max_total_size };
+    let front_matter = match &package {
+        Some(package) if options.exclude_front_matter => front_matter(zip, package, options),
+        _ => Vec::new()
+    };
+
+    if let Some(spine) = package.as_ref().map(|p| &p.spine).filter(|s| !s.is_empty()) {
+        debug!("按 spine 顺序读取 {} 个内容文档", spine.len());
+        let spine = match options.chapter_range {
+            Some((start, end)) if end > spine.len() => {
+                bail!("章节范围 {}:{} 超出了内容文档的数量（共 {} 个）", start, end, spine.len());
+            }
+            Some((start, end)) => &spine[start - 1..end],
+            None => &spine[..]
+        };
+        for name in spine {
+            if !options.is_selected(name) {
+                debug!("{} 不匹配 --chapter-filter，已跳过", name);
+                continue;
+            }
+            if front_matter.contains(name) {
+                debug!("跳过前后附文 {}", name);
+                continue;
+            }
+            match read_zip_entry(zip, name, options.max_entry_size, options.encoding, options.password()) {
+                Ok(content) => {
+                    budget.add(content.len())?;
+                    if each(ContentDocument { name: name.clone(), content }).is_break() {
+                        break;
+                    }
+                }
+                Err(e) => warn!("{}{:#}，已跳过", source, e)
+            }
+        }
+        return Ok(());
+    }
+
+    if options.chapter_range.is_some() {
+        bail!("没有可用的 spine，无法按章节序号选择内容文档");
+    }
+
+    let n = zip.len();
+    debug!("没有可用的 spine，遍历 zip 中的 {} 个条目", n);
+
+    for i in 0..n {
+        let name = zip.name_for_index(i).context("遍历zip文件列表时出现错误")?;
+
+        let lower = name.to_ascii_lowercase();
+        if !(lower.ends_with(".xhtml") || lower.ends_with(".html")) {
+            continue;
+        }
+        if is_navigation_name(&lower) || package.as_ref().is_some_and(|p| p.is_navigation(name)) {
+            debug!("跳过导航文档 {}", name);
+            continue;
+        }
+        if !options.is_selected(name) {
+            debug!("{} 不匹配 --chapter-filter，已跳过", name);
+            continue;
+        }
+        if front_matter.iter().any(|p| p == name) {
+            debug!("跳过前后附文 {}", name);
+            continue;
+        }
+
+        let name = name.to_string();
+        let file = match zip_entry(zip, i, options.password()) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("{}读取 {} 时出现错误：{:#}，已跳过", source, name, e);
+                continue;
+            }
+        };
+        let size = file.size();
+        match read_content(file, &name, size, options.max_entry_size, options.encoding) {
+            Ok(content) => {
+                budget.add(content.len())?;
+                if each(ContentDocument { name, content }).is_break() {
+                    break;
+                }
+            }
+            Err(e) => warn!("{}读取 {} 时出现错误：{:#}，已跳过", source, name, e)
+        }
+    }
+
+    Ok(())
+}
+
+/// 没有 OPF 时按文件名识别的导航文档，不含扩展名
+const NAVIGATION_NAMES: [&str; 3] = ["toc", "nav", "contents"];
+
+
+/// 小写的 zip 内路径的文件名是否为常见的导航文档名，如 `OEBPS/TOC.xhtml`
+fn is_navigation_name(lower: &str) -> bool
+{
+    let file_name = lower.rsplit('/').next().unwrap_or(lower);
+    let stem = file_name.strip_suffix(".xhtml").or_else(|| file_name.strip_suffix(".html")).unwrap_or(file_name);
+    NAVIGATION_NAMES.contains(&stem)
+}
+
+
+/// 前后附文：OPF guide 中的引用加上 EPUB3 导航文档 landmarks 中的链接
+fn front_matter<W: Read + Seek>(zip: &mut ZipArchive<W>, package: &opf::Package, options: &CountOptions) -> Vec<String>
+{
+    static LANDMARKS: OnceLock<Selector> = OnceLock::new();
+    let landmarks = LANDMARKS.get_or_init(|| {
+        Selector::parse(r#"nav[epub\:type~="landmarks"] a[href]"#).expect("landmarks 选择器无效")
+    });
+
+    let mut paths = package.front_matter.clone();
+    for nav in package.navigation.iter().filter(|p| p.ends_with(".xhtml") || p.ends_with(".html")) {
+        let Ok(content) = read_zip_entry(zip, nav, options.max_entry_size, None, options.password()) else {
+            continue;
+        };
+        let base = match nav.rfind('/') {
+            Some(i) => &nav[..=i],
+            None => ""
+        };
+        let document = Html::parse_document(&content);
+        paths.extend(document.select(landmarks)
+            .filter(|a| a.value().attr("epub:type").is_some_and(opf::is_front_matter_type))
+            .filter_map(|a| a.value().attr("href"))
+            .map(|href| opf::resolve_href(base, href)));
+    }
+    debug!("前后附文：{:?}", paths);
+    paths
+}
+
+
+/// 统计一个 EPUB 文件的总字数、各章字数与元数据
+pub fn get_epub_word_count<P: AsRef<Path>>(path: P) -> Result<WordCountReport>
+{
+    let path = path.as_ref();
+    let options = CountOptions::default();
+    let source = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    // 章节与元数据从同一个 ZipArchive 读取，文件只打开一次
+    let mut zip = open_zip(open_file(path, &options)?)?;
+    let chapters = count_zip_chapters(&mut zip, &source, &options)?;
+    let total: u64 = chapters.iter().map(
+        |c| c.word_count
+    ).sum::<u64>();
+    debug!("{}：{} 个内容文档，共 {} 字", path.display(), chapters.len(), total);
+    let metadata = read_package(&mut zip, &options).map(|p| p.metadata).unwrap_or_default();
+
+    Ok(WordCountReport {
+        total,
+        per_chapter: chapters.into_iter().map(|c| (c.name, c.word_count)).collect(),
+        title: metadata.title,
+        language: metadata.language
+    })
+}
+
+
+/// 统计一个 EPUB 文件的总字数
+pub fn get_epub_word_count_total<P: AsRef<Path>>(path: P) -> Result<u64>
+{
+    Ok(get_epub_word_count(path)?.total)
+}
+
+
+/// 按阅读顺序统计 EPUB 中每个内容文档的字数
+///
+/// 各内容文档在当前 rayon 线程池中并行解析；在 `ThreadPool::install` 内调用时
+/// 与外层的文件级并行共用同一个线程池，不会额外创建线程。
+pub fn get_epub_chapter_word_counts<P: AsRef<Path>>(path: P, options: &CountOptions) -> Result<Vec<ChapterWordCount>>
+{
+    let path = path.as_ref();
+    let source = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    count_epub_chapters(open_file(path, options)?, &source, options)
+}
+
+
+/// 一批并行统计的内容文档的总大小上限
+///
+/// 读满一批（或文档数达到线程数）就统计并释放，书很大时不会把所有章节同时留在内存里。
+const BATCH_SIZE: usize = 16 * 1024 * 1024;
+
+
+/// 按阅读顺序统计已打开的 EPUB（如内存中的数据）中每个内容文档的字数
+///
+/// `source` 用于警告信息中指出是哪本书，可以为空。
+pub fn count_epub_chapters<W: Read + Seek>(file: W, source: &str, options: &CountOptions) -> Result<Vec<ChapterWordCount>>
+{
+    count_zip_chapters(&mut open_zip(file)?, source, options)
+}
+
+
+/// [`count_epub_chapters`] 的实现，读取已打开的 zip，调用方之后还可以从中读取其他内容
+fn count_zip_chapters<W: Read + Seek>(zip: &mut ZipArchive<W>, source: &str, options: &CountOptions) -> Result<Vec<ChapterWordCount>>
+{
+    let count_batch = |batch: Vec<ContentDocument>| -> Vec<ChapterWordCount> {
+        batch.into_par_iter().map(|doc| {
+            let (text, paragraphs) = html_text_paragraphs(&doc.content, options);
+            let mut chapter = count_text(doc.name, &text, options);
+            if options.structure {
+                chapter.paragraphs = Some(paragraphs);
+            }
+            chapter
+        }).collect()
+    };
+
+    let mut chapters = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_size = 0;
+    read_archive(zip, source, options, |doc| {
+        batch_size += doc.content.len();
+        batch.push(doc);
+        if batch_size >= BATCH_SIZE || batch.len() >= rayon::current_num_threads() {
+            chapters.extend(count_batch(std::mem::take(&mut batch)));
+            batch_size = 0;
+        }
+        ControlFlow::Continue(())
+    })?;
+    chapters.extend(count_batch(batch));
+    Ok(chapters)
+}
+
+
+/// 统计一个 UTF-8 纯文本文件的字数
+pub fn get_txt_word_count<P: AsRef<Path>>(path: P, options: &CountOptions) -> Result<u64>
+{
+    Ok(count_txt_file(path, options)?.word_count)
+}
+
+
+/// 把一个 UTF-8 纯文本文件当作单个文档统计
+pub fn count_txt_file<P: AsRef<Path>>(path: P, options: &CountOptions) -> Result<ChapterWordCount>
+{
+    let path = path.as_ref();
+    let mut file = open_file(path, options)?;
+    let content = match options.encoding {
+        Some(forced) => {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes).context("读取文本文件时出现错误")?;
+            encoding::decode_with(&bytes, Some(forced))
+        }
+        None => {
+            let mut content = String::new();
+            file.read_to_string(&mut content).context("读取文本文件时出现错误")?;
+            content
+        }
+    };
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok(count_plain_text(name, &content, options))
+}
+
+
+/// 按扩展名选择统计方式：`.txt` 按纯文本统计，其余按 EPUB 统计
+pub fn get_word_count<P: AsRef<Path>>(path: P) -> Result<u64>
+{
+    if is_txt(&path) {
+        get_txt_word_count(path, &CountOptions::default())
+    } else {
+        get_epub_word_count_total(path)
+    }
+}
+
+
+/// 重试之间等待时间的上限
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+
+/// 是否为值得重试的暂时性错误，如网络文件系统上的 `EAGAIN`、`EIO`
+fn is_transient(e: &std::io::Error) -> bool
+{
+    if matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+        return true;
+    }
+    #[cfg(unix)]
+    if matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EIO)) {
+        return true;
+    }
+    false
+}
+
+
+/// 打开文件，遇到暂时性错误时重试 `retries` 次，每次等待的时间翻倍，最长 [`MAX_RETRY_DELAY`]
+///
+/// 文件不存在、没有权限等其他错误不会重试。
+fn open_with_retries(path: &Path, retries: u32) -> std::io::Result<File>
+{
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 0;
+    loop {
+        match OpenOptions::new().read(true).write(false).create(false).open(path) {
+            Ok(file) => return Ok(file),
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                debug!("{}：打开失败（{}），{} ms 后第 {} 次重试", path.display(), e, delay.as_millis(), attempt);
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+
+/// 以只读方式打开文件
+///
+/// `options.mmap` 为真时优先使用 mmap，失败时退回普通文件读取。
+pub fn open_file<P: AsRef<Path>>(p: P, options: &CountOptions) -> Result<Box<dyn ReadSeek>>
+{
+    let path = p.as_ref();
+    let file = open_with_retries(path, options.retries).context("打开文件失败")?;
+    // 长度为 0 的文件在部分平台上无法 mmap
+    if !options.mmap || file.metadata().is_ok_and(|m| m.len() == 0) {
+        return Ok(Box::new(BufReader::new(file)));
+    }
+    let file_mmap = unsafe { Mmap::map(&file) };
+    match file_mmap {
+        Ok(mmap) => {
+            debug!("{}：使用 mmap 读取", path.display());
+            Ok(Box::new(Cursor::new(mmap)))
+        }
+        Err(e) => {
+            debug!("{}：mmap 失败（{}），改用普通读取", path.display(), e);
+            Ok(Box::new(BufReader::new(file)))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn cjk_characters_and_latin_words()
+    {
+        assert_eq!(Tokens::new("Hello 世界 world").collect::<Vec<_>>(), ["Hello", "世", "界", "world"]);
+        assert_eq!(Tokens::new("Hello 世界 world").count(), 4);
+        assert_eq!(Tokens::new("don't e-mail 中文。").count(), 5);
+    }
+
+    #[test]
+    fn style_and_script_are_not_counted()
+    {
+        let xhtml = r#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>第一章</title>
+<style type="text/css">body { font-family: serif; } p.note { color: red; }</style></head>
+<body><p>正文</p><script>var note = "不计";</script></body></html>"#;
+        assert_eq!(html_word_count(xhtml), 2);
+    }
+
+    /// 在内存中打包一个 EPUB：`files` 为 `OEBPS/` 下的路径与内容，`opf` 写入 `OEBPS/content.opf`
+    fn epub(opf: &str, files: &[(&str, &[u8])]) -> Cursor<Vec<u8>>
+    {
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        zip.start_file(opf::CONTAINER_PATH, stored).unwrap();
+        zip.write_all(br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#).unwrap();
+        zip.start_file("OEBPS/content.opf", stored).unwrap();
+        zip.write_all(opf.as_bytes()).unwrap();
+        for (name, content) in files {
+            zip.start_file(format!("OEBPS/{}", name), stored).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        let mut file = zip.finish().unwrap();
+        file.rewind().unwrap();
+        file
+    }
+
+    #[test]
+    fn nav_document_is_not_counted()
+    {
+        let opf = r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+<manifest>
+  <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+  <item id="c1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+</manifest>
+<spine><itemref idref="nav"/><itemref idref="c1"/></spine>
+</package>"#;
+        let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<body><nav epub:type="toc"><ol><li><a href="chapter1.xhtml">第一章 开始</a></li></ol></nav></body></html>"#;
+        let chapter = "<html><body><p>正文内容</p></body></html>";
+        let file = epub(opf, &[("nav.xhtml", nav.as_bytes()), ("chapter1.xhtml", chapter.as_bytes())]);
+        let documents = zip_xhtml_read(file, &CountOptions::default()).unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].name, "OEBPS/chapter1.xhtml");
+        assert_eq!(html_word_count(&documents[0].content), 4);
+    }
+
+    #[test]
+    fn ncx_document_is_not_counted()
+    {
+        let opf = r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+<manifest>
+  <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+  <item id="c1" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+</manifest>
+<spine toc="ncx"><itemref idref="ncx"/><itemref idref="c1"/></spine>
+</package>"#;
+        let ncx = r#"<?xml version="1.0" encoding="utf-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+<docTitle><text>测试书名</text></docTitle>
+<navMap><navPoint id="p1" playOrder="1"><navLabel><text>第一章 开始</text></navLabel><content src="chapter1.xhtml"/></navPoint></navMap>
+</ncx>"#;
+        let chapter = "<html><body><p>正文内容</p></body></html>";
+        let file = epub(opf, &[("toc.ncx", ncx.as_bytes()), ("chapter1.xhtml", chapter.as_bytes())]);
+        let documents = zip_xhtml_read(file, &CountOptions::default()).unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(html_word_count(&documents[0].content), 4);
+    }
+
+    /// 把 `chapters` 依次打包为 `chN.xhtml` 并写入 spine
+    fn chapters_epub<C: AsRef<[u8]>>(chapters: &[C]) -> Cursor<Vec<u8>>
+    {
+        let names: Vec<String> = (1..=chapters.len()).map(|i| format!("ch{}.xhtml", i)).collect();
+        let manifest: String = names.iter().enumerate()
+            .map(|(i, name)| format!(r#"<item id="c{}" href="{}" media-type="application/xhtml+xml"/>"#, i, name))
+            .collect();
+        let spine: String = (0..names.len()).map(|i| format!(r#"<itemref idref="c{}"/>"#, i)).collect();
+        let opf = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+<manifest>{}</manifest><spine>{}</spine></package>"#, manifest, spine);
+        let files: Vec<(&str, &[u8])> = names.iter().map(String::as_str).zip(chapters.iter().map(AsRef::as_ref)).collect();
+        epub(&opf, &files)
+    }
+
+    #[test]
+    fn gbk_chapter_is_decoded()
+    {
+        let (body, _, _) = encoding_rs::GBK.encode("<html><body><p>简体中文</p></body></html>");
+        let mut chapter = br#"<?xml version="1.0" encoding="GBK"?>"#.to_vec();
+        chapter.extend_from_slice(&body);
+        let documents = zip_xhtml_read(chapters_epub(&[chapter]), &CountOptions::default()).unwrap();
+        assert_eq!(html_word_count(&documents[0].content), 4);
+    }
+
+    #[test]
+    fn entities_are_decoded()
+    {
+        let xhtml = "<html><body><p>one&nbsp;two&#8212;three &amp;#x4e2d;&amp;#25991; caf&eacute;&#x3000;&lt;b&gt;</p></body></html>";
+        let text = html_text(xhtml);
+        assert_eq!(text.trim(), "one\u{a0}two\u{2014}three 中文 café\u{3000}<b>");
+        assert_eq!(html_word_count(xhtml), 7);
+    }
+
+    #[test]
+    fn block_boundaries_separate_words()
+    {
+        let paragraphs = [
+            "It was the best of times, it was the worst of times.",
+            "Call me Ishmael.",
+            "Happy families are all alike"
+        ];
+        let xhtml = format!(
+            "<html><body><h1>Openings</h1><p>{}</p><p>{}</p><div>{}</div><ul><li>one</li><li>two</li></ul>last<br/>line</body></html>",
+            paragraphs[0], paragraphs[1], paragraphs[2]
+        );
+        let expected = paragraphs.iter().map(|p| p.split_whitespace().count()).sum::<usize>() + 5;
+        assert_eq!(html_word_count(&xhtml), expected as u64);
+        assert_eq!(html_word_count("<p>Hello</p><p>World</p>"), 2);
+    }
+
+    #[test]
+    fn kobo_spans_do_not_change_counts()
+    {
+        let plain = "<html><body><p>It was a bright cold day in April, and the clocks were striking thirteen.</p>\
+<p>我们都是行路人。</p></body></html>";
+        let kepub = r#"<html><body><div id="book-columns"><div id="book-inner">
+<p><span class="koboSpan" id="kobo.1.1">It was a bri</span><span class="koboSpan" id="kobo.1.2">ght cold day in April, </span><span class="koboSpan" id="kobo.1.3">and the clocks were striking thirteen.</span></p>
+<p><span class="koboSpan" id="kobo.2.1">我们都是</span><span class="koboSpan" id="kobo.2.2">行路人。</span></p>
+</div></div></body></html>"#;
+        let options = CountOptions::default();
+        let plain = zip_xhtml_read(chapters_epub(&[plain]), &options).unwrap();
+        let kepub = zip_xhtml_read(chapters_epub(&[kepub]), &options).unwrap();
+        assert_eq!(html_word_count(&plain[0].content), 22);
+        assert_eq!(html_word_count(&kepub[0].content), html_word_count(&plain[0].content));
+    }
+
+    #[test]
+    fn ruby_readings_are_skipped_by_default()
+    {
+        let xhtml = "<html><body><p><ruby>漢字<rp>(</rp><rt>かんじ</rt><rp>)</rp></ruby>を<ruby>読<rt>よ</rt></ruby>む</p></body></html>";
+        let options = CountOptions::default();
+        assert_eq!(html_text_with(xhtml, &options).trim(), "漢字を読む");
+        assert_eq!(html_count(xhtml, &options), 5);
+        let keep = CountOptions { keep_ruby: true, ..CountOptions::default() };
+        assert_eq!(html_count(xhtml, &keep), 9);
+    }
+
+    #[test]
+    fn nested_parallelism_stays_in_one_pool()
+    {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let chapter = "<html><body><p>第一段正文。</p><p>Second paragraph here.</p></body></html>";
+        let huge = chapters_epub(&vec![chapter; 200]).into_inner();
+        let small = chapters_epub(&[chapter, chapter]).into_inner();
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = started.clone();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .start_handler(move |_| { counter.fetch_add(1, Ordering::SeqCst); })
+            .build()
+            .unwrap();
+        let options = CountOptions::default();
+        let (huge_total, small_total): (u64, u64) = pool.install(|| {
+            let huge_total = count_epub_chapters(Cursor::new(&huge), "", &options).unwrap()
+                .iter().map(|c| c.word_count).sum();
+            let small_total = (0..50).into_par_iter()
+                .map(|_| count_epub_chapters(Cursor::new(&small), "", &options).unwrap())
+                .flatten()
+                .map(|c| c.word_count)
+                .sum();
+            (huge_total, small_total)
+        });
+        drop(pool);
+
+        // 一本大书与许多小书都只用到线程池自己的 2 个线程
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        assert_eq!(huge_total, 200 * 9);
+        assert_eq!(small_total, huge_total / 2);
+    }
+
+    #[test]
+    fn full_width_space_and_punctuation()
+    {
+        let text = "你好，世界。\u{3000}\u{3000}这是　测试！hello,\u{3000}world";
+        assert_eq!(Tokens::new(text).collect::<Vec<_>>(),
+            ["你", "好", "，", "世", "界", "。", "这", "是", "测", "试", "！", "hello", "world"]);
+        assert_eq!(text_word_count(text), 13);
+        assert_eq!(text_word_count("\u{3000}\u{3000}"), 0);
+        assert_eq!(text_word_count("“引号”……——"), 2);
+        let punctuation = CountOptions { include_punctuation: true, ..CountOptions::default() };
+        assert_eq!(text_count(text, &punctuation), 14);
+        assert_eq!(text_count("“引号”……——", &punctuation), 8);
+    }
+
+    #[test]
+    fn html_word_count_accepts_str()
+    {
+        let html = "<html><body><p>你好，world</p></body></html>";
+        let owned = html.to_string();
+        assert_eq!(html_word_count(html), 4);
+        assert_eq!(html_word_count(&owned), html_word_count(&owned[..]));
+        assert_eq!(html_word_count(&owned[..owned.find("world").unwrap()]), 3);
+    }
+
+    #[test]
+    fn script_counts_full_width_latin()
+    {
+        let counts = script_counts("ＡＢＣ ａｂ 中文 abc １２，");
+        assert_eq!(counts.latin, 8);
+        assert_eq!(counts.cjk, 2);
+        assert_eq!(counts.digits, 2);
+        assert_eq!(counts.other, 1);
+    }
+
+    #[test]
+    fn tokens_keep_combining_marks_in_words()
+    {
+        let nfd = "e\u{301}te\u{301} cafe\u{301}";
+        assert_eq!(Tokens::new(nfd).collect::<Vec<_>>(), ["e\u{301}te\u{301}", "cafe\u{301}"]);
+        assert_eq!(Tokens::new(nfd).count(), Tokens::with_unit(nfd, CountUnit::Graphemes).count());
+        assert_eq!(Tokens::new("ok\u{200D}ay").count(), 1);
+        // 单独的组合符号不计数
+        assert_eq!(Tokens::new("\u{301} 中\u{301}").count(), 1);
+    }
+
+    #[test]
+    fn only_transient_open_errors_are_retried()
+    {
+        assert!(is_transient(&std::io::Error::from(ErrorKind::Interrupted)));
+        assert!(is_transient(&std::io::Error::from(ErrorKind::TimedOut)));
+        assert!(!is_transient(&std::io::Error::from(ErrorKind::NotFound)));
+        assert!(!is_transient(&std::io::Error::from(ErrorKind::PermissionDenied)));
+        assert!(!is_transient(&std::io::Error::from(ErrorKind::IsADirectory)));
+        #[cfg(unix)]
+        {
+            assert!(is_transient(&std::io::Error::from_raw_os_error(libc::EIO)));
+            assert!(is_transient(&std::io::Error::from_raw_os_error(libc::EAGAIN)));
+            assert!(!is_transient(&std::io::Error::from_raw_os_error(libc::ENOENT)));
+        }
+    }
+
+    #[test]
+    fn detected_language_is_bcp47()
+    {
+        let zh = "这是一段用来识别语言的中文文本，内容足够长，统计识别的结果才会可靠。我们今天去公园散步，看到很多人在跑步。";
+        assert_eq!(detect_language(zh).as_deref(), Some("zh"));
+        let en = "This is a reasonably long English sentence that should be detected reliably by the statistical language detector.";
+        assert_eq!(detect_language(en).as_deref(), Some("en"));
+        assert!(ISO_639_1.iter().all(|(iso3, iso1)| whatlang::Lang::from_code(*iso3).is_some() && iso1.len() == 2));
+        assert_eq!(ISO_639_1.len(), whatlang::Lang::all().len());
+    }
+
+    #[test]
+    fn word_count_report_includes_metadata()
+    {
+        let opf = r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>测试</dc:title></metadata>
+<manifest><item id="c1" href="ch1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let chapter = "<html><body><p>正文内容 and words</p></body></html>";
+        let file = epub(opf, &[("ch1.xhtml", chapter.as_bytes())]);
+        let path = std::env::temp_dir().join(format!("epub-count-{}-report.epub", std::process::id()));
+        std::fs::write(&path, file.into_inner()).unwrap();
+        let report = get_epub_word_count(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let report = report.unwrap();
+        assert_eq!(report.total, 6);
+        assert_eq!(report.per_chapter, [("OEBPS/ch1.xhtml".to_string(), 6)]);
+        assert_eq!(report.title.as_deref(), Some("测试"));
+    }
+}
@@ -1,15 +1,67 @@
-use std::fs::{OpenOptions};
-use std::io::{Cursor, Read, Seek};
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{stdin, stdout, BufReader, BufWriter, Cursor, IsTerminal, Read, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
 use std::thread;
-use std::thread::{available_parallelism, JoinHandle};
+use std::thread::available_parallelism;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use encoding_rs::Encoding;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::Level;
+use rayon::prelude::*;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rayon::ThreadPoolBuilder;
+use regex::Regex;
+use scraper::Selector;
+use serde::Deserialize;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use serde_json::{json, Map, Value};
+
+use epub_count::{
+    count_epub_chapters, count_plain_text, count_txt_file, get_all_books_walkdir, get_epub_chapter_word_counts,
+    detect_language, get_epub_image_count, get_epub_metadata, get_epub_text_sample, html_text,
+    is_cbz, is_txt, merge_frequencies, zip_metadata_read_with, zip_image_count, zip_text_sample, ChapterWordCount, CountMode, CountOptions,
+    CountUnit, Encrypted, FileWordCount, Frequencies, ScriptCounts, WalkOptions
+};
+use epub_count::archive::{content_digest, is_tar, read_tar_members, tar_members};
+use epub_count::encoding::decode_with;
+use epub_count::walk::ExcludeSet;
+
+mod cache;
+mod config;
+mod memory;
+mod watch;
+
+use cache::Cache;
+use memory::MemoryLimit;
+
+/// 部分输入不存在或统计失败
+const EXIT_PARTIAL_FAILURE: i32 = 1;
+/// 没有找到任何可统计的文件
+const EXIT_NO_INPUT: i32 = 2;
+/// 除 DRM 加密的文件外全部统计成功
+const EXIT_ENCRYPTED: i32 = 3;
+/// 被 Ctrl-C 中断（128 + SIGINT）
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// 收到第一次 Ctrl-C 后置位，不再分派新的文件
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// `--fail-fast` 时第一个文件统计失败后置位，其余工作线程不再开始新的文件
+static ABORTED: AtomicBool = AtomicBool::new(false);
 
-use clap::Parser;
-use zip::ZipArchive;
-use scraper::Html;
-use walkdir::{DirEntry, WalkDir};
-use memmap2::Mmap;
 
 /// 一个用于统计 EPUB 文件字数的小工具
 ///
@@ -19,12 +71,14 @@ use memmap2::Mmap;
     version,
     about,
     long_about = None,
+    after_help = "退出码：\n  0  全部统计成功\n  1  部分文件不存在或统计失败\n  2  没有找到任何可统计的文件\n  3  部分文件被 DRM 加密，无法统计\n  130  被 Ctrl-C 中断，只输出了已统计部分的结果",
 )]
 struct Cli
 {
     /// 要统计的 EPUB 文件路径（支持多个）
     ///
-    /// 可传入 `.epub` 文件，或配合 `-w` 传入目录。
+    /// 可传入 `.epub` 文件，或配合 `-w` 传入目录；`http://`/`https://` 开头的地址会先下载到内存。
+    /// 只传入 `-` 时从标准输入逐行读取路径。
     #[arg(required = true)]
     files: Vec<String>,
 
@@ -40,167 +94,2000 @@ struct Cli
     stream_output: bool,
 
 
-    /// 调整使用的线程数，默认为cpu线程数
+    /// 调整使用的线程数，默认为环境变量 `EPUB_COUNT_THREADS` 或cpu线程数
     #[arg(short, long, default_value_t = get_cpu_count())]
-    cpu_nums: usize
+    cpu_nums: usize,
+
+
+    /// 线程数不超过待统计的文件数
+    ///
+    /// 只统计少量文件时可以避免创建用不上的线程；但单本书的各章节也会并行统计，
+    /// 因此默认不启用。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    threads_auto_cap: bool,
+
+
+    /// 输出格式
+    ///
+    /// `json` 模式下标准输出只包含一个 JSON 对象，错误信息仍输出到标准错误。
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+
+    /// `--format json` 时输出带缩进的 JSON，内容与紧凑格式相同
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    json_pretty: bool,
+
+
+    /// 结果排序方式，默认保持输入/遍历顺序
+    ///
+    /// 字数相同时按文件名排序。
+    #[arg(long, value_enum)]
+    sort: Option<SortOrder>,
+
+
+    /// 不显示进度条
+    ///
+    /// 进度条输出到标准错误，标准输出不是终端时也会自动关闭。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    no_progress: bool,
+
+
+    /// 只输出总字数，不输出每个文件的结果
+    #[arg(short, long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    quiet: bool,
+
+
+    /// 显示预计阅读时间，参数为每分钟阅读的字数（默认 250）
+    #[arg(
+        long,
+        value_name = "WPM",
+        num_args = 0..=1,
+        default_missing_value = "250",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    reading_speed: Option<u64>,
+
+
+    /// 估算阅读时间时 CJK 文字每分钟阅读的字数，默认与 `--reading-speed` 相同
+    ///
+    /// 给出时按文字种类分开估算：汉字、假名与谚文按此速度，其余的词按 `--reading-speed`。
+    #[arg(long, value_name = "CPM", requires = "reading_speed", value_parser = clap::value_parser!(u64).range(1..))]
+    reading_speed_cjk: Option<u64>,
+
+
+    /// 遍历目录时同时统计 `.txt` 纯文本文件
+    ///
+    /// 直接传入的 `.txt` 文件总是按纯文本统计。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    include_txt: bool,
+
+
+    /// 遍历目录时匹配的扩展名，可以多次指定，如 `--ext epub --ext kepub`，默认为 `epub`
+    #[arg(long = "ext", value_name = "EXT")]
+    ext: Vec<String>,
+
+
+    /// 额外输出每个内容文档（章节）的字数
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    by_chapter: bool,
+
+
+    /// 显示 OPF 元数据中的书名与作者
+    ///
+    /// 没有元数据时仍只显示文件名。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    show_title: bool,
+
+
+    /// 只列出字数不少于该值的文件
+    #[arg(long, value_name = "N")]
+    min_words: Option<u64>,
+
+
+    /// 只列出字数不超过该值的文件
+    #[arg(long, value_name = "N")]
+    max_words: Option<u64>,
+
+
+    /// 总字数只统计 `--min-words`/`--max-words` 范围内的文件
+    ///
+    /// 默认总字数仍包含所有文件。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    filtered_total: bool,
+
+
+    /// 遍历目录时排除匹配该 glob 的文件（可重复）
+    ///
+    /// 模式匹配完整路径，或不区分大小写地匹配文件名，例如 `--exclude '*_sample.epub'`。
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+
+    /// 从标准输入读取路径时以 NUL 分隔，配合 `find -print0` 使用
+    #[arg(short = '0', long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    null: bool,
+
+
+    /// 遍历目录的最大深度，1 表示只统计目录下的直接文件，默认不限制
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(usize))]
+    max_depth: Option<usize>,
+
+
+    /// 遍历目录时只统计在此之后修改过的文件
+    ///
+    /// 可以是 ISO 8601 日期或时间（如 `2024-05-01`、`2024-05-01T08:00:00+08:00`，
+    /// 不带时区时按本地时间），也可以是相对现在的时间（如 `7d`、`12h`，单位为 s/m/h/d/w）。
+    #[arg(long, value_name = "TIME")]
+    after: Option<String>,
+
+
+    /// 遍历目录时只统计在此之前修改的文件，格式同 `--after`
+    #[arg(long, value_name = "TIME")]
+    before: Option<String>,
+
+
+    /// 遍历目录时跟随符号链接
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    follow_symlinks: bool,
+
+
+    /// 多线程并行遍历目录，在网络文件系统等较慢的存储上能更快找到所有文件
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    parallel_walk: bool,
+
+
+    /// 遍历目录时不读取根目录下的 `.epubignore`
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    no_ignore: bool,
+
+
+    /// 只列出将要统计的文件，不实际统计
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    dry_run: bool,
+
+
+    /// 文本输出中的字数不使用千位分隔符
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    no_group: bool,
+
+
+    /// 单个内容文档解压后的大小上限（MB），超过时跳过该文档
+    #[arg(long, value_name = "MB", default_value_t = 64)]
+    max_entry_size: u64,
+
+
+    /// 单个 EPUB 中内容文档解压后的总大小上限（MB），超过时该文件统计失败
+    #[arg(long, value_name = "MB", default_value_t = 1024)]
+    max_total_size: u64,
+
+
+    /// 同时统计的文件总大小上限（MB），超过时等待其他文件统计完成后再开始新的文件
+    ///
+    /// 按文件大小估算内存占用；单个文件超过上限时仍会单独统计。
+    #[arg(long, value_name = "MB")]
+    max_memory: Option<u64>,
+
+
+    /// 不使用 mmap，直接读取文件
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    no_mmap: bool,
+
+
+    /// 输出调试日志到标准错误，相当于 `RUST_LOG=debug`
+    ///
+    /// 设置了 `RUST_LOG` 时以环境变量为准。
+    #[arg(short, long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    verbose: bool,
+
+
+    /// 额外统计每个文件的词汇量（不重复的词/字数）
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    unique: bool,
+
+
+    /// 在每个文件下列出出现次数最多的 N 个词
+    ///
+    /// CJK 文本按单字统计。
+    #[arg(long, value_name = "N")]
+    top_words: Option<usize>,
+
+
+    /// 额外输出用 jieba 分词统计的中文词数
+    ///
+    /// 分词较慢，默认仍按单字统计。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    segment: bool,
+
+
+    /// 在总计下方输出文件数、平均数、中位数以及最少/最多字数的文件
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    stats: bool,
+
+
+    /// 在总计下方按字数区间输出文件数的直方图
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    histogram: bool,
+
+
+    /// `--histogram` 的区间边界，用逗号分隔，如 `10000,50000,100000`
+    #[arg(long, value_name = "N,...", value_delimiter = ',', default_values_t = [10_000u64, 50_000, 100_000])]
+    buckets: Vec<u64>,
+
+
+    /// 按所在目录分组输出，每组附带小计，组按目录路径排序
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    group_by_dir: bool,
+
+
+    /// 按 OPF 中的作者分组输出，每组附带小计，组按作者名排序
+    ///
+    /// 有多位作者的书按完整的作者列表（如 `甲、乙`）单独成组，各组小计之和等于总计；
+    /// 没有作者信息的书（包括 `.txt`）归入“未知作者”。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue, conflicts_with = "group_by_dir")]
+    group_by_author: bool,
+
+
+    /// 只统计作者包含该字符串的书（不区分大小写）
+    #[arg(long, value_name = "NAME")]
+    author: Option<String>,
+
+
+    /// 字数缓存文件，修改时间与大小都没变的文件直接使用缓存的字数
+    ///
+    /// 需要分章、词频或分词结果时仍会重新统计，缓存在结束时写回；非默认的 `--count`/`--count-mode`、`--max-entry-size`/`--max-total-size`，或指定 `--password`、`--strict` 时不使用缓存。
+    #[arg(long, value_name = "PATH")]
+    cache: Option<PathBuf>,
+
+
+    /// 把结果写入文件而不是标准输出，进度与错误仍输出到标准错误
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+
+    /// 与 `--output` 一起使用，追加到已有文件末尾而不是覆盖
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue, requires = "output")]
+    append: bool,
+
+
+    /// 同时统计另一个目录（或文件），按文件名配对后输出每个文件的字数差
+    ///
+    /// 指定 `--show-title` 时文件名配不上的文件再按书名配对。
+    #[arg(long, value_name = "PATH")]
+    diff: Option<PathBuf>,
+
+
+    /// `--format table` 中文件名列的最大显示宽度，超出部分以省略号截断
+    #[arg(long, value_name = "N", default_value_t = 40)]
+    max_name_width: usize,
+
+
+    /// 分词的最小单位，`graphemes` 按字素簇处理组合附加符号等多码点字符，速度较慢
+    #[arg(long, value_enum, default_value_t = CountBy::Chars)]
+    count: CountBy,
+
+
+    /// 计数方式：`chars` 中日韩文字按字、其余按词，`words` 按空白分词，`bytes` 按 UTF-8 字节数
+    #[arg(long, value_enum, default_value_t = CountModeArg::Chars)]
+    count_mode: CountModeArg,
+
+
+    /// 同时统计注音（`<rt>`/`<rp>` 中的振假名等），默认只统计被注音的正文
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    keep_ruby: bool,
+
+
+    /// 随机抽取最多 N 个文件统计，并按比例估算全部文件的总字数
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    sample: Option<usize>,
+
+
+    /// `--sample` 的随机种子，相同种子总是抽到相同的文件
+    #[arg(long, value_name = "SEED", requires = "sample")]
+    seed: Option<u64>,
+
+
+    /// 输出每个文件占总字数的百分比，需要等全部文件统计完成后才能输出
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    percent: bool,
+
+
+    /// 输出文件的完整路径（相对于输入参数）而不是文件名，JSON 中额外增加 `path` 字段
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    paths: bool,
+
+
+    /// 输出每个文件的统计耗时，结束时在标准错误输出总耗时
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    timing: bool,
+
+
+    /// 显示每本书的语言
+    ///
+    /// 优先读取 OPF 中的 `<dc:language>`，没有时根据正文用统计方法识别；两者都是 BCP 47 标签（如 `zh-CN`、`en`）。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    detect_lang: bool,
+
+
+    /// 第一个文件统计失败时立即停止，报告该文件并以非零状态退出
+    ///
+    /// 默认跳过失败的文件继续统计其余文件。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    fail_fast: bool,
+
+
+    /// 每统计完 N 个文件在标准错误输出一次累计总字数，省略 N 时为 100
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "100",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    live_total: Option<usize>,
+
+
+    /// 内容完全相同的文件（硬链接或复制）只统计一次，并报告跳过的重复文件数
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    dedup: bool,
+
+
+    /// 只统计 zip 内路径匹配该正则表达式的内容文档，如 `chap[0-9]+`
+    ///
+    /// 用于按命名约定排除版权页、附录等前后附文。
+    #[arg(long, value_name = "REGEX")]
+    chapter_filter: Option<String>,
+
+
+    /// 只统计匹配该 CSS 选择器的元素中的文本，如 `div.chapter-body`
+    ///
+    /// 默认统计整个文档中 `<script>`、`<style>` 以外的文本。
+    #[arg(long, value_name = "CSS")]
+    content_selector: Option<String>,
+
+
+    /// 不统计 OPF `<guide>` 与 EPUB3 landmarks 中标记为封面、版权页、目录、献词等的文档
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    exclude_front_matter: bool,
+
+
+    /// 同时统计 `<head>` 中的文本（如 `<title>`）
+    ///
+    /// 默认只统计 `<body>`，`<script>`、`<style>` 始终不计入。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    include_head: bool,
+
+
+    /// 把所有标点与符号都计入字数
+    ///
+    /// 空白（包括全角空格）从不计数。默认只统计全角标点（如 `，。「」`），
+    /// 半角标点与 `“”……——` 等通用标点不计；启用后词以外的每个标点、符号都计为一个字，
+    /// `--count-mode words` 时只由标点组成的片段也计为一个词。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    include_punctuation: bool,
+
+
+    /// 解密 zip 加密（ZipCrypto 或 AES）条目的密码
+    ///
+    /// 只用于普通的 zip 密码保护，与 DRM 无关。不提供密码时加密的条目会被跳过并给出警告。
+    #[arg(long, value_name = "PASSWORD")]
+    password: Option<String>,
+
+
+    /// 只统计 spine 阅读顺序中的第 START 到 END 个内容文档（从 1 开始，包含两端）
+    ///
+    /// 只给出一个数字时只统计该章；范围超出书中内容文档数量时报错。对 `.txt` 无效。
+    #[arg(long, value_name = "START:END")]
+    chapters: Option<String>,
+
+
+    /// 同时统计每个 EPUB 中的图片数量（jpg/png/gif/svg/webp）
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    count_images: bool,
+
+
+    /// 同时统计段落数与句子数
+    ///
+    /// 段落为直接包含文本的块级元素（`.txt` 中为非空行），句子按 `。！？.!?` 切分。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    structure: bool,
+
+
+    /// 同时输出每个文件的内容文档（章节）数，`.txt` 计为 1
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    chapter_count: bool,
+
+
+    /// 同时按文字种类（CJK、拉丁字母、数字、其他字符）统计字符数，用于发现未翻译的段落
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    script_breakdown: bool,
+
+
+    /// 把 OPF 中 `<dc:description>`（简介）的字数计入总字数，并单独列出
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    count_metadata: bool,
+
+
+    /// 强制按该编码解码所有内容文档与 `.txt`，如 `gbk`、`big5`、`shift_jis`
+    ///
+    /// 用于编码声明缺失或错误的文件，指定后不再根据 BOM 与声明识别编码。
+    #[arg(long, value_name = "LABEL")]
+    encoding: Option<String>,
+
+
+    /// 打开文件遇到暂时性错误（如网络存储抖动）时的重试次数，每次重试的等待时间翻倍，最长 2 秒
+    #[arg(long, default_value_t = 3, value_name = "N")]
+    retries: u32,
+
+
+    /// 下载 `http://`/`https://` 输入的超时时间（秒）
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    timeout: u64,
+
+
+    /// 单个 `http://`/`https://` 输入的下载大小上限（MB），超过时该输入下载失败
+    #[arg(long, value_name = "MB", default_value_t = 1024)]
+    max_download: u64,
+
+
+    /// 显示相对于 `BASE` 的路径（隐含 `--paths`），省略 `BASE` 时相对于 `-w` 遍历的目录
+    ///
+    /// 文件不在 `BASE` 下时仍显示完整路径。
+    #[arg(long, value_name = "BASE", num_args = 0..=1)]
+    relative_to: Option<Option<PathBuf>>,
+
+
+    /// `mimetype` 缺失或不正确的文件视为统计失败，默认只打印警告后继续统计
+    ///
+    /// 与 `--warn-empty` 一起使用时，有字数为 0 的文件也以非零状态退出。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    strict: bool,
+
+
+    /// 统计结束后在标准错误输出中列出字数为 0 的文件（如只有图片的书）
+    ///
+    /// 不影响总字数；不与 `--strict` 一起使用时也不影响退出码。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    warn_empty: bool,
+
+
+    /// 监视输入文件与目录，有书籍变化时清屏并重新统计
+    ///
+    /// 连续的多次写入会合并为一次统计。只能在命令行上使用，按 Ctrl-C 退出。
+    #[arg(long, default_value_t = false, action = clap::ArgAction::SetTrue)]
+    watch: bool,
+
+
+    /// 配置文件路径，默认为 `~/.config/epub-count/config.toml`
+    ///
+    /// 配置文件的键与参数同名（用下划线代替连字符），命令行参数优先。
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputFormat
+{
+    /// 人类可读的文本
+    Text,
+    /// 单个 JSON 对象
+    Json,
+    /// 带表头的 CSV
+    Csv,
+    /// 列对齐的表格，数字右对齐
+    Table,
+    /// GitHub 风格的 Markdown 表格
+    Markdown,
+    /// 独立的 HTML 页面，点击表头可以排序
+    Html,
+    /// 每行一个 JSON 对象，文件统计完成即输出，最后一行为汇总
+    Ndjson
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SortOrder
+{
+    /// 按文件名
+    Name,
+    /// 按字数升序
+    CountAsc,
+    /// 按字数降序
+    CountDesc
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum CountBy
+{
+    /// 按字符（Unicode 标量值）
+    Chars,
+    /// 按扩展字素簇
+    Graphemes
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum CountModeArg
+{
+    /// 中日韩文字按字、其余按词
+    Chars,
+    /// 按空白分隔的词
+    Words,
+    /// 提取文本的 UTF-8 字节数
+    Bytes
+}
+
+
+/// 解析 `--chapters` 的 `START:END` 或单个 `N`
+fn parse_chapter_range(s: &str) -> Option<(usize, usize)>
+{
+    let (start, end) = s.split_once(':').unwrap_or((s, s));
+    let (start, end): (usize, usize) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    (1 <= start && start <= end).then_some((start, end))
+}
+
+
+/// 解析 `--after`/`--before` 的时间，格式无法识别时返回 `None`
+fn parse_time(s: &str) -> Option<SystemTime>
+{
+    let s = s.trim();
+    parse_absolute_time(s).or_else(|| parse_relative_time(s))
+}
+
+
+/// RFC 3339 或本地时区的 `YYYY-MM-DD[ HH:MM[:SS]]`
+fn parse_absolute_time(s: &str) -> Option<SystemTime>
+{
+    if let Ok(time) = DateTime::parse_from_rfc3339(s)
+    {
+        return Some(time.into());
+    }
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)))?;
+    Local.from_local_datetime(&naive).earliest().map(Into::into)
+}
+
+
+/// `7d`、`12h` 之类相对现在的时间
+fn parse_relative_time(s: &str) -> Option<SystemTime>
+{
+    let unit = s.chars().last()?;
+    let n: u64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    let secs = match unit
+    {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None
+    };
+    SystemTime::now().checked_sub(Duration::from_secs(n.checked_mul(secs)?))
+}
+
+
+/// 解析 `--after`/`--before`，无法识别时按参数错误退出
+fn time_arg(s: &str) -> SystemTime
+{
+    parse_time(s).unwrap_or_else(|| {
+        Cli::command().error(ErrorKind::ValueValidation, format!("无法识别的时间：{}", s)).exit()
+    })
 }
 
 
 fn get_cpu_count() -> usize {
+    // 容器里 available_parallelism 可能报告宿主机的全部核心，允许用环境变量覆盖
+    if let Some(n) = env::var("EPUB_COUNT_THREADS").ok().and_then(|v| v.trim().parse::<usize>().ok()) {
+        return n.max(1);
+    }
     available_parallelism()
         .map(|n| n.get())
         .unwrap_or(1)
 }
 
 
-trait ReadSeek: Read + Seek {}
-impl<T: Read + Seek> ReadSeek for T {}
+struct FileData
+{
+    /// 在命令行参数/遍历结果中的原始顺序
+    index: usize,
+    filename: String,
+    file: PathBuf,
+    /// 已经读入内存的内容：下载的文件，或分派时才读出的 tar 成员
+    ///
+    /// 此时 `file` 是 URL，或 tar 路径与成员路径拼接成的虚拟路径。
+    data: Option<Vec<u8>>,
+    /// 通过 `-w` 遍历得到的文件所在的遍历根目录
+    root: Option<PathBuf>,
+    /// 来自 tar 的成员，内容在分派时才按顺序读出
+    member: Option<TarMember>
+}
+
+
+/// tar 中的一个成员在 tar 内的位置
+struct TarMember
+{
+    archive: PathBuf,
+    /// 在 [`tar_members`] 结果中的序号
+    ordinal: usize,
+    size: u64,
+    /// `--dedup` 时列出成员顺便计算的内容哈希
+    digest: Option<u64>
+}
 
 
-struct FileData
+struct FailedFile
 {
+    index: usize,
     filename: String,
-    file: PathBuf
+    error: String,
+    /// 内容被 DRM 加密
+    encrypted: bool
+}
+
+/// 字数之外附加输出的一列
+struct Column
+{
+    /// JSON 字段名与 CSV 表头
+    key: &'static str,
+    /// 文本输出中的名称
+    label: &'static str,
+    value: Value,
+    /// 文本与 CSV 中数字后面的单位，JSON 中只保留数字
+    suffix: &'static str
+}
+
+impl Column
+{
+    fn display(&self) -> String
+    {
+        match &self.value {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            v => format!("{}{}", v, self.suffix)
+        }
+    }
+}
+
+
+/// 根据命令行选项计算单个文件（或总计）的附加列
+///
+/// 缺少数据的列值为 `null`，文本输出中不显示，CSV 中为空。
+/// `grand_total` 为全部文件的总字数，用于计算 `--percent` 的占比；
+/// `has_cbz` 为输入中是否有 `.cbz`，有时各种格式都输出页数列。
+fn columns(args: &Cli, info: &FileWordCount, grand_total: Option<u64>, has_cbz: bool) -> Vec<Column>
+{
+    let mut cols = Vec::new();
+    if args.segment {
+        cols.push(Column {
+            key: "segmented_words",
+            label: "分词词数",
+            value: json!(info.segmented_words),
+            suffix: ""
+        });
+    }
+    if has_cbz {
+        cols.push(Column {
+            key: "pages",
+            label: "页数",
+            value: json!(info.pages),
+            suffix: ""
+        });
+    }
+    if args.count_metadata {
+        cols.push(Column {
+            key: "metadata_words",
+            label: "元数据字数",
+            value: json!(info.metadata_words),
+            suffix: ""
+        });
+    }
+    if args.chapter_count {
+        cols.push(Column {
+            key: "chapter_count",
+            label: "章节数",
+            value: json!(info.chapter_count),
+            suffix: ""
+        });
+    }
+    if args.structure {
+        cols.push(Column {
+            key: "paragraphs",
+            label: "段落",
+            value: json!(info.paragraphs),
+            suffix: ""
+        });
+        cols.push(Column {
+            key: "sentences",
+            label: "句子",
+            value: json!(info.sentences),
+            suffix: ""
+        });
+    }
+    if args.script_breakdown {
+        let scripts = info.scripts.as_ref();
+        let script_cols = [
+            ("script_cjk", "CJK", scripts.map(|s| s.cjk)),
+            ("script_latin", "拉丁字母", scripts.map(|s| s.latin)),
+            ("script_digits", "数字", scripts.map(|s| s.digits)),
+            ("script_other", "其他字符", scripts.map(|s| s.other))
+        ];
+        for (key, label, value) in script_cols {
+            cols.push(Column { key, label, value: json!(value), suffix: "" });
+        }
+    }
+    if args.unique {
+        cols.push(Column {
+            key: "unique_words",
+            label: "词汇量",
+            value: json!(info.frequencies.as_ref().map(|f| f.len())),
+            suffix: ""
+        });
+    }
+    if let Some(speed) = args.reading_speed {
+        cols.push(Column {
+            key: "reading_time",
+            label: "阅读时间",
+            value: Value::String(format_reading_time(
+                info.word_count,
+                info.scripts.map_or(0, |s| s.cjk),
+                speed,
+                args.reading_speed_cjk.unwrap_or(speed)
+            )),
+            suffix: ""
+        });
+    }
+    if args.count_images {
+        cols.push(Column {
+            key: "images",
+            label: "图片",
+            value: json!(info.images),
+            suffix: ""
+        });
+    }
+    if args.detect_lang {
+        cols.push(Column {
+            key: "language",
+            label: "语言",
+            value: json!(info.language),
+            suffix: ""
+        });
+    }
+    if args.timing {
+        cols.push(Column {
+            key: "elapsed_ms",
+            label: "耗时",
+            value: json!(info.elapsed.map(|d| (d.as_secs_f64() * 10000.0).round() / 10.0)),
+            suffix: " ms"
+        });
+    }
+    if args.percent {
+        let percent = grand_total.filter(|&t| t > 0)
+            .map(|t| (info.word_count as f64 * 1000.0 / t as f64).round() / 10.0);
+        cols.push(Column {
+            key: "percent",
+            label: "占比",
+            value: json!(percent),
+            suffix: "%"
+        });
+    }
+    cols
+}
+
+
+/// 把多个文件的结果合计为一行总计
+fn sum_infos<'a>(infos: impl IntoIterator<Item = &'a FileWordCount>) -> FileWordCount
+{
+    let mut total = FileWordCount::default();
+    for info in infos {
+        total.word_count += info.word_count;
+        if let Some(n) = info.segmented_words {
+            *total.segmented_words.get_or_insert(0) += n;
+        }
+        if let Some(n) = info.metadata_words {
+            *total.metadata_words.get_or_insert(0) += n;
+        }
+        if let Some(n) = info.pages {
+            *total.pages.get_or_insert(0) += n;
+        }
+        if let Some(n) = info.chapter_count {
+            *total.chapter_count.get_or_insert(0) += n;
+        }
+        if let Some(n) = info.paragraphs {
+            *total.paragraphs.get_or_insert(0) += n;
+        }
+        if let Some(n) = info.sentences {
+            *total.sentences.get_or_insert(0) += n;
+        }
+        if let Some(n) = info.images {
+            *total.images.get_or_insert(0) += n;
+        }
+        if let Some(scripts) = &info.scripts {
+            total.scripts.get_or_insert_default().add(scripts);
+        }
+        if let Some(frequencies) = &info.frequencies {
+            let all = total.frequencies.get_or_insert_with(Frequencies::new);
+            merge_frequencies(all, frequencies.clone());
+        }
+    }
+    total
+}
+
+
+/// 按所在目录把结果分组，`paths[info.index]` 是该文件的路径
+fn group_by_dir<'a>(infos: &'a [FileWordCount], paths: &'a [PathBuf]) -> BTreeMap<&'a Path, Vec<&'a FileWordCount>>
+{
+    let mut groups: BTreeMap<&Path, Vec<&FileWordCount>> = BTreeMap::new();
+    for info in infos {
+        let dir = paths[info.index].parent().unwrap_or(Path::new(""));
+        groups.entry(dir).or_default().push(info);
+    }
+    groups
+}
+
+
+/// 没有作者信息的书在 `--group-by-author` 中的组名
+const UNKNOWN_AUTHOR: &str = "未知作者";
+
+
+/// `--group-by-dir` 或 `--group-by-author` 的分组，组名用于输出，未分组时返回 `None`
+fn output_groups<'a>(args: &Cli, infos: &'a [FileWordCount], paths: &'a [PathBuf]) -> Option<Vec<(String, Vec<&'a FileWordCount>)>>
+{
+    if args.group_by_dir
+    {
+        let groups = group_by_dir(infos, paths).into_iter()
+            .map(|(dir, group)| (dir.display().to_string(), group))
+            .collect();
+        return Some(groups);
+    }
+    if args.group_by_author
+    {
+        let mut groups: BTreeMap<String, Vec<&FileWordCount>> = BTreeMap::new();
+        for info in infos {
+            let author = if info.creators.is_empty() { UNKNOWN_AUTHOR.to_string() } else { info.creators.join("、") };
+            groups.entry(author).or_default().push(info);
+        }
+        return Some(groups.into_iter().collect());
+    }
+    None
+}
+
+
+/// 是否有作者包含 `--author` 给出的名字（不区分大小写）
+fn matches_author(info: &FileWordCount, name: &str) -> bool
+{
+    let name = name.to_lowercase();
+    info.creators.iter().any(|c| c.to_lowercase().contains(&name))
+}
+
+
+/// 按 `--author` 筛选，`--diff` 时两侧都要筛选
+fn retain_author(args: &Cli, infos: &mut Vec<FileWordCount>)
+{
+    if let Some(author) = &args.author {
+        infos.retain(|info| matches_author(info, author));
+    }
+}
+
+
+/// `--diff` 的配对结果
+struct Diff<'a>
+{
+    matched: Vec<(&'a FileWordCount, &'a FileWordCount)>,
+    only_left: Vec<&'a FileWordCount>,
+    only_right: Vec<&'a FileWordCount>
+}
+
+
+/// 先按文件名配对，`by_title` 时剩下的再按书名配对，同名文件按出现顺序一一对应
+fn diff<'a>(left: &'a [FileWordCount], right: &'a [FileWordCount], by_title: bool) -> Diff<'a>
+{
+    let mut remaining: Vec<Option<&FileWordCount>> = right.iter().map(Some).collect();
+    let mut pairs: Vec<Option<&FileWordCount>> = vec![None; left.len()];
+
+    let keys: [fn(&FileWordCount) -> Option<&str>; 2] = [
+        |info| Some(info.filename.as_str()),
+        |info| info.title.as_deref()
+    ];
+    for key in keys.iter().take(if by_title { 2 } else { 1 }) {
+        let mut index: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, r) in remaining.iter().enumerate().rev() {
+            if let Some(k) = r.and_then(key) {
+                index.entry(k).or_default().push(i);
+            }
+        }
+        for (l, pair) in left.iter().zip(pairs.iter_mut()).filter(|(_, p)| p.is_none()) {
+            if let Some(i) = key(l).and_then(|k| index.get_mut(k)).and_then(|v| v.pop()) {
+                *pair = remaining[i].take();
+            }
+        }
+    }
+
+    let mut result = Diff { matched: Vec::new(), only_left: Vec::new(), only_right: remaining.into_iter().flatten().collect() };
+    for (l, pair) in left.iter().zip(pairs) {
+        match pair {
+            Some(r) => result.matched.push((l, r)),
+            None => result.only_left.push(l)
+        }
+    }
+    result
+}
+
+
+/// 序列化一个完整的 JSON 输出，`--json-pretty` 时带缩进；NDJSON 每行必须是单行，始终紧凑输出
+fn json_text(args: &Cli, value: &Value) -> String
+{
+    if args.json_pretty && args.format == OutputFormat::Json
+    {
+        serde_json::to_string_pretty(value).expect("序列化JSON时出现错误")
+    }
+    else
+    {
+        value.to_string()
+    }
+}
+
+
+fn diff_report(args: &Cli, other: &Path, diff: &Diff) -> String
+{
+    let delta = |l: &FileWordCount, r: &FileWordCount| r.word_count as i64 - l.word_count as i64;
+    let entry = |info: &FileWordCount| json!({ "filename": info.filename, "word_count": info.word_count });
+    match args.format
+    {
+        OutputFormat::Text | OutputFormat::Table | OutputFormat::Markdown | OutputFormat::Html => {
+            let mut lines: Vec<String> = diff.matched.iter()
+                .map(|(l, r)| format!(
+                    "{}：{} -> {}（{:+}）",
+                    display_name(l), format_amount(args, l.word_count), format_amount(args, r.word_count), delta(l, r)
+                ))
+                .collect();
+            lines.extend(diff.only_left.iter()
+                .map(|l| format!("仅在输入中：{} {}", display_name(l), format_amount(args, l.word_count))));
+            lines.extend(diff.only_right.iter()
+                .map(|r| format!("仅在 {} 中：{} {}", other.display(), display_name(r), format_amount(args, r.word_count))));
+            lines.join("\n")
+        }
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            let matched: Vec<Value> = diff.matched.iter()
+                .map(|(l, r)| json!({
+                    "filename": l.filename,
+                    "other_filename": r.filename,
+                    "word_count": l.word_count,
+                    "other_word_count": r.word_count,
+                    "delta": delta(l, r)
+                }))
+                .collect();
+            json_text(args, &json!({
+                "matched": matched,
+                "only_in_input": diff.only_left.iter().map(|l| entry(l)).collect::<Vec<_>>(),
+                "only_in_other": diff.only_right.iter().map(|r| entry(r)).collect::<Vec<_>>()
+            }))
+        }
+        OutputFormat::Csv => {
+            let mut lines = vec!["filename,word_count,other_word_count,delta".to_string()];
+            lines.extend(diff.matched.iter()
+                .map(|(l, r)| format!("{},{},{},{}", csv_escape(shown_name(l)), l.word_count, r.word_count, delta(l, r))));
+            lines.extend(diff.only_left.iter()
+                .map(|l| format!("{},{},,", csv_escape(shown_name(l)), l.word_count)));
+            lines.extend(diff.only_right.iter()
+                .map(|r| format!("{},,{},", csv_escape(shown_name(r)), r.word_count)));
+            lines.join("\n")
+        }
+    }
+}
+
+
+/// 字数分布的汇总统计
+struct Stats<'a>
+{
+    files: usize,
+    average: f64,
+    median: f64,
+    min: &'a FileWordCount,
+    max: &'a FileWordCount
+}
+
+
+/// 计算 `infos` 的汇总统计，没有文件时返回 `None`
+fn stats(infos: &[FileWordCount]) -> Option<Stats<'_>>
+{
+    let min = infos.iter().min_by_key(|info| info.word_count)?;
+    let max = infos.iter().max_by_key(|info| info.word_count)?;
+    let mut counts: Vec<u64> = infos.iter().map(|info| info.word_count).collect();
+    counts.sort_unstable();
+    let mid = counts.len() / 2;
+    let median = if counts.len().is_multiple_of(2) {
+        (counts[mid - 1] + counts[mid]) as f64 / 2.0
+    } else {
+        counts[mid] as f64
+    };
+    Some(Stats {
+        files: infos.len(),
+        average: counts.iter().sum::<u64>() as f64 / infos.len() as f64,
+        median,
+        min,
+        max
+    })
+}
+
+
+fn stats_text(args: &Cli, stats: &Stats) -> String
+{
+    format!(
+        "文件数：{} 平均：{} 中位数：{}\n最少：{} {}\n最多：{} {}",
+        stats.files,
+        format_amount(args, stats.average.round() as u64),
+        format_amount(args, stats.median.round() as u64),
+        display_name(stats.min),
+        format_amount(args, stats.min.word_count),
+        display_name(stats.max),
+        format_amount(args, stats.max.word_count)
+    )
+}
+
+
+/// 直方图的一个区间，`max` 不含在区间内，最后一个区间没有上界
+struct Bucket
+{
+    min: u64,
+    max: Option<u64>,
+    files: usize
+}
+
+
+/// 按 `--buckets` 的边界统计每个区间内的文件数
+fn histogram(args: &Cli, infos: &[FileWordCount]) -> Vec<Bucket>
+{
+    let mut bounds: Vec<u64> = args.buckets.iter().copied().filter(|&b| b > 0).collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+    let mut buckets: Vec<Bucket> = std::iter::once(0).chain(bounds.iter().copied())
+        .zip(bounds.iter().copied().map(Some).chain([None]))
+        .map(|(min, max)| Bucket { min, max, files: 0 })
+        .collect();
+    for info in infos {
+        let i = bounds.partition_point(|&b| b <= info.word_count);
+        buckets[i].files += 1;
+    }
+    buckets
+}
+
+
+/// 直方图的文本形式，条形长度与文件数成正比
+fn histogram_text(args: &Cli, buckets: &[Bucket]) -> String
+{
+    const BAR_WIDTH: usize = 40;
+    let labels: Vec<String> = buckets.iter()
+        .map(|b| match b.max {
+            Some(max) => format!("{}-{}", format_count(args, b.min), format_count(args, max)),
+            None => format!("{}+", format_count(args, b.min))
+        })
+        .collect();
+    let label_width = labels.iter().map(|l| l.width()).max().unwrap_or(0);
+    let most = buckets.iter().map(|b| b.files).max().unwrap_or(0).max(1);
+    buckets.iter().zip(&labels)
+        .map(|(b, label)| {
+            let bar = "█".repeat((b.files * BAR_WIDTH).div_ceil(most));
+            format!("{}  {} {}", pad(label, label_width, false), bar, b.files)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
+fn histogram_json(buckets: &[Bucket]) -> Value
+{
+    buckets.iter()
+        .map(|b| json!({ "min": b.min, "max": b.max, "files": b.files }))
+        .collect()
+}
+
+
+fn stats_json(stats: &Stats) -> Value
+{
+    json!({
+        "files": stats.files,
+        "average": stats.average,
+        "median": stats.median,
+        "min": { "filename": stats.min.filename, "word_count": stats.min.word_count },
+        "max": { "filename": stats.max.filename, "word_count": stats.max.word_count }
+    })
+}
+
+
+/// 按每分钟 `speed` 字估算阅读时间，格式为 `Xh Ym`
+///
+/// `word_count` 中的 `cjk` 个 CJK 字按每分钟 `cjk_speed` 字计算。
+fn format_reading_time(word_count: u64, cjk: u64, speed: u64, cjk_speed: u64) -> String
+{
+    let cjk = cjk.min(word_count);
+    let minutes = ((word_count - cjk) * cjk_speed + cjk * speed).div_ceil(speed * cjk_speed);
+    format!("{}h {}m", minutes / 60, minutes % 60)
+}
+
+
+/// 按千位分组格式化数字，例如 `328914` -> `328,914`
+fn group_digits(n: u64) -> String
+{
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+
+/// 文本输出中的字数，默认千位分组
+fn format_count(args: &Cli, n: u64) -> String
+{
+    if args.no_group { n.to_string() } else { group_digits(n) }
+}
+
+
+/// 带单位的计数，单位随 `--count-mode` 变化
+fn format_amount(args: &Cli, n: u64) -> String
+{
+    let unit = match args.count_mode
+    {
+        CountModeArg::Chars => "字",
+        CountModeArg::Words => "词",
+        CountModeArg::Bytes => "字节"
+    };
+    format!("{} {}", format_count(args, n), unit)
+}
+
+
+fn text_line(args: &Cli, prefix: &str, word_count: u64, cols: &[Column]) -> String
+{
+    let mut line = format!("{}：{}", prefix, format_amount(args, word_count));
+    for col in cols.iter().filter(|c| !c.value.is_null()) {
+        line.push_str(&format!(" {}：{}", col.label, col.display()));
+    }
+    line
+}
+
+
+/// 输出中代表文件的名字：`--paths` 时为完整路径，否则为文件名
+fn shown_name(info: &FileWordCount) -> &str
+{
+    info.path.as_deref().unwrap_or(&info.filename)
+}
+
+
+/// 文本输出中的文件名，有书名时显示为 `书名 - 作者（文件名）`
+fn display_name(info: &FileWordCount) -> String
+{
+    match &info.title {
+        Some(title) if info.creators.is_empty() => format!("{}（{}）", title, shown_name(info)),
+        Some(title) => format!("{} - {}（{}）", title, info.creators.join("、"), shown_name(info)),
+        None => shown_name(info).to_string()
+    }
+}
+
+
+/// 单个文件的文本输出，`--by-chapter` 时在其下方缩进列出各章节
+fn file_text(args: &Cli, info: &FileWordCount, grand_total: Option<u64>, has_cbz: bool) -> String
+{
+    let cols = columns(args, info, grand_total, has_cbz);
+    let mut text = match info.pages
+    {
+        // 漫画只有页数，字数没有意义
+        Some(pages) => {
+            let mut line = format!("{} 页数：{} 页", display_name(info), format_count(args, pages));
+            for col in cols.iter().filter(|c| c.key != "pages" && !c.value.is_null()) {
+                line.push_str(&format!(" {}：{}", col.label, col.display()));
+            }
+            line
+        }
+        None => text_line(args, &format!("{} 字数", display_name(info)), info.word_count, &cols)
+    };
+    for chapter in info.chapters.iter().flatten() {
+        text.push_str(&format!("\n    {} 字数：{}", chapter.name, format_amount(args, chapter.word_count)));
+    }
+    for (word, n) in top_words(args, info) {
+        text.push_str(&format!("\n    {}：{} 次", word, format_count(args, n)));
+    }
+    text
+}
+
+
+/// `--top-words` 要列出的高频词，按次数降序、次数相同时按词排序
+fn top_words<'a>(args: &Cli, info: &'a FileWordCount) -> Vec<(&'a str, u64)>
+{
+    let (Some(n), Some(frequencies)) = (args.top_words, &info.frequencies) else {
+        return Vec::new();
+    };
+    let mut words: Vec<(&str, u64)> = frequencies.iter().map(|(w, &c)| (w.as_str(), c)).collect();
+    words.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    words.truncate(n);
+    words
+}
+
+
+/// JSON 中的附加列，各类文字的字符数与各章一样合并为嵌套的 `scripts` 对象
+fn json_columns(args: &Cli, info: &FileWordCount, grand_total: Option<u64>, has_cbz: bool) -> Vec<(String, Value)>
+{
+    let mut values: Vec<(String, Value)> = columns(args, info, grand_total, has_cbz).into_iter()
+        .filter(|col| !col.key.starts_with("script_"))
+        .map(|col| (col.key.to_string(), col.value))
+        .collect();
+    if args.script_breakdown {
+        values.push(("scripts".to_string(), json!(info.scripts)));
+    }
+    values
+}
+
+
+fn json_file(args: &Cli, info: &FileWordCount, grand_total: Option<u64>, has_cbz: bool) -> Value
+{
+    let mut obj = match serde_json::to_value(info).expect("序列化JSON时出现错误") {
+        Value::Object(obj) => obj,
+        _ => Map::new()
+    };
+    for (key, value) in json_columns(args, info, grand_total, has_cbz) {
+        obj.insert(key, value);
+    }
+    if args.top_words.is_some() {
+        let words: Vec<Value> = top_words(args, info).into_iter()
+            .map(|(word, count)| json!({"word": word, "count": count}))
+            .collect();
+        obj.insert("top_words".to_string(), Value::Array(words));
+    }
+    Value::Object(obj)
+}
+
+
+/// 按显示宽度截断，超出 `max` 时以省略号结尾
+fn truncate_width(s: &str, max: usize) -> String
+{
+    if s.width() <= max {
+        return s.to_string();
+    }
+    let mut out = String::new();
+    let mut width = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if width + w + 1 > max {
+            break;
+        }
+        out.push(c);
+        width += w;
+    }
+    out.push('…');
+    out
+}
+
+
+/// 按显示宽度补齐到 `width`，CJK 字符按两列计算
+fn pad(s: &str, width: usize, right: bool) -> String
+{
+    let fill = " ".repeat(width.saturating_sub(s.width()));
+    if right { format!("{}{}", fill, s) } else { format!("{}{}", s, fill) }
+}
+
+
+/// 表格中要显示的列：总计或任一文件有值的列才显示，例如耗时只有文件行有值
+fn shown_columns(args: &Cli, infos: &[FileWordCount], total: &FileWordCount, has_cbz: bool) -> Vec<Column>
+{
+    let grand_total = Some(total.word_count);
+    columns(args, total, grand_total, has_cbz).into_iter()
+        .filter(|c| {
+            !c.value.is_null() || infos.iter().any(|info| {
+                columns(args, info, grand_total, has_cbz).iter().any(|col| col.key == c.key && !col.value.is_null())
+            })
+        })
+        .collect()
+}
+
+
+/// 表格一行中 `keys` 对应各列的显示值
+fn column_cells(args: &Cli, info: &FileWordCount, grand_total: Option<u64>, has_cbz: bool, keys: &[&str]) -> Vec<String>
+{
+    let cols = columns(args, info, grand_total, has_cbz);
+    keys.iter().map(|k| cols.iter().find(|c| c.key == *k).map(Column::display).unwrap_or_default()).collect()
+}
+
+
+/// `--format table` 的输出：文件名左对齐，字数及其余列右对齐，最后是总计行
+fn table(args: &Cli, infos: &[FileWordCount], total: &FileWordCount, has_cbz: bool) -> String
+{
+    let grand_total = Some(total.word_count);
+    let shown = shown_columns(args, infos, total, has_cbz);
+    let keys: Vec<&str> = shown.iter().map(|c| c.key).collect();
+    let row = |name: String, info: &FileWordCount| -> Vec<String> {
+        let mut cells = vec![truncate_width(&name, args.max_name_width), format_count(args, info.word_count)];
+        cells.extend(column_cells(args, info, grand_total, has_cbz, &keys));
+        cells
+    };
+
+    let mut header = vec!["文件".to_string(), "字数".to_string()];
+    header.extend(shown.iter().map(|c| c.label.to_string()));
+    let rows: Vec<Vec<String>> = if args.quiet { Vec::new() } else {
+        infos.iter().map(|info| row(display_name(info), info)).collect()
+    };
+    let total_row = row("总计".to_string(), total);
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter().chain([&header, &total_row]).map(|r| r[i].width()).max().unwrap_or(0))
+        .collect();
+    let line = |cells: &[String]| -> String {
+        cells.iter().zip(&widths).enumerate()
+            .map(|(i, (cell, &w))| pad(cell, w, i > 0))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    let separator = widths.iter().map(|&w| "-".repeat(w)).collect::<Vec<_>>().join("  ");
+
+    let mut lines = vec![line(&header), separator.clone()];
+    lines.extend(rows.iter().map(|r| line(r)));
+    if !rows.is_empty() {
+        lines.push(separator);
+    }
+    lines.push(line(&total_row));
+    lines.join("\n")
+}
+
+
+/// GitHub 风格的 Markdown 表格，总计行加粗
+fn markdown(args: &Cli, infos: &[FileWordCount], total: &FileWordCount, has_cbz: bool) -> String
+{
+    let grand_total = Some(total.word_count);
+    let shown = shown_columns(args, infos, total, has_cbz);
+    let keys: Vec<&str> = shown.iter().map(|c| c.key).collect();
+    let row = |name: String, info: &FileWordCount| -> Vec<String> {
+        let mut cells = vec![name, format_count(args, info.word_count)];
+        cells.extend(column_cells(args, info, grand_total, has_cbz, &keys));
+        cells
+    };
+    let line = |cells: &[String]| format!("| {} |", cells.iter().map(|c| markdown_escape(c)).collect::<Vec<_>>().join(" | "));
+
+    let mut header = vec!["文件".to_string(), "字数".to_string()];
+    header.extend(shown.iter().map(|c| c.label.to_string()));
+    let separator = std::iter::once("---").chain(std::iter::repeat_n("---:", header.len() - 1)).collect::<Vec<_>>();
+
+    let mut lines = vec![line(&header), format!("| {} |", separator.join(" | "))];
+    if !args.quiet {
+        lines.extend(infos.iter().map(|info| line(&row(display_name(info), info))));
+    }
+    let total_row: Vec<String> = row("总计".to_string(), total).into_iter()
+        .map(|c| if c.is_empty() { c } else { format!("**{}**", c) })
+        .collect();
+    lines.push(line(&total_row));
+    lines.join("\n")
+}
+
+
+/// HTML 报告的页面框架，`{title}`、`{table}` 与 `{footer}` 在输出时替换
+const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="zh">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.8em; }
+th { background: #f3f3f3; cursor: pointer; user-select: none; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+tfoot td { font-weight: bold; }
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{table}
+{footer}
+<script>
+document.querySelectorAll("thead th").forEach((th, i) => {
+  th.addEventListener("click", () => {
+    const body = th.closest("table").tBodies[0];
+    const asc = th.dataset.order !== "asc";
+    th.dataset.order = asc ? "asc" : "desc";
+    const key = row => row.cells[i].dataset.sort ?? row.cells[i].textContent;
+    const rows = Array.from(body.rows).sort((a, b) => {
+      const x = key(a), y = key(b);
+      const d = (x !== "" && y !== "" && !isNaN(x) && !isNaN(y)) ? x - y : x.localeCompare(y);
+      return asc ? d : -d;
+    });
+    rows.forEach(row => body.appendChild(row));
+  });
+});
+</script>
+</body>
+</html>"#;
+
+
+/// 独立的 HTML 报告，文件名列按文字排序、其余列按数值排序
+fn html_report(args: &Cli, infos: &[FileWordCount], total: &FileWordCount, has_cbz: bool, footer: &[String]) -> String
+{
+    let grand_total = Some(total.word_count);
+    let shown = shown_columns(args, infos, total, has_cbz);
+    let keys: Vec<&str> = shown.iter().map(|c| c.key).collect();
+    let row = |name: String, info: &FileWordCount| -> String {
+        let cols = columns(args, info, grand_total, has_cbz);
+        let mut cells = vec![
+            format!("<td>{}</td>", html_escape::encode_text(&name)),
+            format!("<td class=\"num\" data-sort=\"{}\">{}</td>", info.word_count, format_count(args, info.word_count))
+        ];
+        cells.extend(keys.iter().map(|k| match cols.iter().find(|c| c.key == *k) {
+            Some(c) if c.value.is_number() => {
+                format!("<td class=\"num\" data-sort=\"{}\">{}</td>", c.value, html_escape::encode_text(&c.display()))
+            }
+            Some(c) => format!("<td>{}</td>", html_escape::encode_text(&c.display())),
+            None => "<td></td>".to_string()
+        }));
+        format!("<tr>{}</tr>", cells.concat())
+    };
+
+    let header: String = ["文件", "字数"].into_iter()
+        .chain(shown.iter().map(|c| c.label))
+        .map(|label| format!("<th>{}</th>", label))
+        .collect();
+    let rows: Vec<String> = if args.quiet { Vec::new() } else {
+        infos.iter().map(|info| row(display_name(info), info)).collect()
+    };
+    let table = format!(
+        "<table>\n<thead><tr>{}</tr></thead>\n<tbody>\n{}\n</tbody>\n<tfoot>{}</tfoot>\n</table>",
+        header, rows.join("\n"), row("总计".to_string(), total)
+    );
+    let footer = if footer.is_empty() { String::new() } else {
+        format!("<pre>{}</pre>", html_escape::encode_text(&footer.join("\n")))
+    };
+    HTML_TEMPLATE
+        .replace("{title}", "字数统计")
+        .replace("{table}", &table)
+        .replace("{footer}", &footer)
+}
+
+
+/// 转义 Markdown 表格单元格中的 `|`，换行替换为空格
+fn markdown_escape(cell: &str) -> String
+{
+    cell.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+
+/// 按 CSV 规则转义字段：含逗号、引号或换行时用双引号包裹，内部引号加倍
+fn csv_escape(field: &str) -> String
+{
+    if field.contains([',', '"', '\n', '\r'])
+    {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else
+    {
+        field.to_string()
+    }
+}
+
+
+/// 字数是否落在 `--min-words`/`--max-words` 范围内
+fn in_word_range(args: &Cli, word_count: u64) -> bool
+{
+    args.min_words.is_none_or(|min| word_count >= min)
+        && args.max_words.is_none_or(|max| word_count <= max)
+}
+
+
+/// 从标准输入读取路径列表，每行一个（`null` 时以 NUL 分隔），忽略空项
+fn read_stdin_paths(null: bool) -> Vec<String>
+{
+    let mut input = Vec::new();
+    if let Err(e) = stdin().read_to_end(&mut input)
+    {
+        eprintln!("读取标准输入时出现错误：{}", e);
+    }
+    let separator = if null { b'\0' } else { b'\n' };
+    input.split(|&b| b == separator)
+        .map(|line| if null { line } else { line.strip_suffix(b"\r").unwrap_or(line) })
+        .filter(|line| !line.is_empty())
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect()
+}
+
+
+/// 初始化日志，默认只输出警告，`-v` 时输出本工具的调试信息
+fn init_logger(verbose: bool)
+{
+    let default = if verbose { "epub_count=debug,warn" } else { "warn" };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default))
+        .format(|buf, record| {
+            let level = match record.level()
+            {
+                Level::Error => "错误",
+                Level::Warn => "警告",
+                Level::Info => "信息",
+                Level::Debug => "调试",
+                Level::Trace => "跟踪"
+            };
+            writeln!(buf, "{}：{}", level, record.args())
+        })
+        .init();
+}
+
+
+/// 由命令行选项构造库的统计参数
+fn count_options(args: &Cli) -> CountOptions
+{
+    CountOptions {
+        max_entry_size: args.max_entry_size.saturating_mul(1024 * 1024),
+        max_total_size: args.max_total_size.saturating_mul(1024 * 1024),
+        mmap: !args.no_mmap,
+        collect_frequencies: args.unique || args.top_words.is_some(),
+        segment: args.segment,
+        strict: args.strict,
+        unit: match args.count
+        {
+            CountBy::Chars => CountUnit::Chars,
+            CountBy::Graphemes => CountUnit::Graphemes
+        },
+        mode: match args.count_mode
+        {
+            CountModeArg::Chars => CountMode::Chars,
+            CountModeArg::Words => CountMode::Words,
+            CountModeArg::Bytes => CountMode::Bytes
+        },
+        keep_ruby: args.keep_ruby,
+        chapter_filter: args.chapter_filter.as_deref().map(|pattern| {
+            Regex::new(pattern).unwrap_or_else(|e| {
+                Cli::command().error(ErrorKind::ValueValidation, format!("无效的正则表达式：{}", e)).exit()
+            })
+        }),
+        retries: args.retries,
+        structure: args.structure,
+        encoding: args.encoding.as_deref().map(|label| {
+            Encoding::for_label(label.trim().as_bytes()).unwrap_or_else(|| {
+                Cli::command().error(ErrorKind::ValueValidation, format!("未知的编码：{}", label)).exit()
+            })
+        }),
+        content_selector: args.content_selector.as_deref().map(|css| {
+            // scraper 的错误信息对使用者没有帮助，只报告选择器本身
+            Selector::parse(css).unwrap_or_else(|_| {
+                Cli::command().error(ErrorKind::ValueValidation, format!("无效的 CSS 选择器：{}", css)).exit()
+            })
+        }),
+        exclude_front_matter: args.exclude_front_matter,
+        include_head: args.include_head,
+        include_punctuation: args.include_punctuation,
+        script_breakdown: args.script_breakdown || args.reading_speed_cjk.is_some(),
+        password: args.password.clone(),
+        chapter_range: args.chapters.as_deref().map(|range| {
+            parse_chapter_range(range).unwrap_or_else(|| {
+                Cli::command().error(
+                    ErrorKind::ValueValidation,
+                    format!("无效的章节范围：{}，应为 START:END，且 1 <= START <= END", range)
+                ).exit()
+            })
+        })
+    }
+}
+
+
+/// 统计单个文件，返回其中每个文档的结果（`.txt` 视为单个文档）
+fn count_file(options: &CountOptions, f: &FileData) -> anyhow::Result<Vec<ChapterWordCount>>
+{
+    if let Some(data) = &f.data
+    {
+        if is_txt(&f.file)
+        {
+            let text = match options.encoding
+            {
+                Some(forced) => decode_with(data, Some(forced)),
+                None => String::from_utf8_lossy(data).into_owned()
+            };
+            return Ok(vec![count_plain_text(f.filename.clone(), &text, options)]);
+        }
+        return count_epub_chapters(Cursor::new(data), &f.filename, options);
+    }
+    if is_txt(&f.file)
+    {
+        return Ok(vec![count_txt_file(&f.file, options)?]);
+    }
+    get_epub_chapter_word_counts(&f.file, options)
+}
+
+
+/// `--fail-fast` 遇到第一个统计失败的文件时报告并退出
+fn fail_fast_exit(f: &FailedFile) -> !
+{
+    eprintln!("错误：{} 统计失败：{}", f.filename, f.error);
+    exit(if f.encrypted { EXIT_ENCRYPTED } else { EXIT_PARTIAL_FAILURE })
+}
+
+
+/// `--paths`/`--relative-to` 时显示的路径，无法去掉前缀时显示完整路径
+fn shown_path(args: &Cli, f: &FileData) -> Option<String>
+{
+    let base = match &args.relative_to
+    {
+        None if !args.paths => return None,
+        None => None,
+        Some(Some(base)) => Some(base.as_path()),
+        Some(None) => f.root.as_deref()
+    };
+    let path = base.and_then(|base| f.file.strip_prefix(base).ok()).unwrap_or(&f.file);
+    Some(path.display().to_string())
+}
+
+
+/// 文件占用内存的估计值：内存中的文件取其长度，tar 成员取 tar 头中的大小，其余取文件大小
+fn file_size(f: &FileData) -> u64
+{
+    match (&f.data, &f.member)
+    {
+        (Some(data), _) => data.len() as u64,
+        (None, Some(member)) => member.size,
+        (None, None) => std::fs::metadata(&f.file).map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+
+/// 把 HTTP(S) 地址的文件下载到内存，返回 URL 路径的最后一段作为文件名
+///
+/// 超过 `limit` 字节时报错，服务器声明的长度不可信，实际读取时同样限制长度。
+fn fetch_url(url: &str, timeout: Duration, limit: u64) -> anyhow::Result<(String, Vec<u8>)>
+{
+    let parsed = reqwest::Url::parse(url).context("无效的 URL")?;
+    let filename = parsed.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(parsed.host_str().unwrap_or(url))
+        .to_string();
+    let client = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()
+        .context("创建 HTTP 客户端时出现错误")?;
+    let response = client.get(parsed).send()?.error_for_status()?;
+    if let Some(len) = response.content_length().filter(|&len| len > limit)
+    {
+        anyhow::bail!("文件大小 {} 字节超过下载上限 {} 字节", len, limit);
+    }
+    let mut data = Vec::new();
+    response.take(limit + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > limit
+    {
+        anyhow::bail!("下载的内容超过上限 {} 字节", limit);
+    }
+    Ok((filename, data))
+}
+
+
+/// 去掉内容完全相同的文件，只保留最先出现的一个，并重新编排序号
+///
+/// 先按大小分组，只有大小相同的文件才读取内容比较哈希，硬链接与复制的文件都算重复。
+/// tar 成员的哈希在列出成员时已经算好。无法读取的文件原样保留，留给统计时报告错误。
+fn dedup(files: Vec<FileData>) -> Vec<FileData>
+{
+    let sizes: Vec<u64> = files.iter().map(file_size).collect();
+    let mut same_size: HashMap<u64, usize> = HashMap::new();
+    for &size in &sizes
+    {
+        *same_size.entry(size).or_insert(0) += 1;
+    }
+
+    let mut seen = HashSet::new();
+    let mut unique = Vec::with_capacity(files.len());
+    for (f, size) in files.into_iter().zip(sizes)
+    {
+        if same_size[&size] > 1
+        {
+            let digest = match (&f.data, &f.member)
+            {
+                (Some(data), _) => content_digest(data.as_slice()).ok(),
+                (None, Some(member)) => member.digest,
+                (None, None) => File::open(&f.file).and_then(|file| content_digest(BufReader::new(file))).ok()
+            };
+            if digest.is_some_and(|digest| !seen.insert((size, digest)))
+            {
+                continue;
+            }
+        }
+        unique.push(f);
+    }
+    for (i, f) in unique.iter_mut().enumerate()
+    {
+        f.index = i;
+    }
+    unique
 }
 
-struct FileWordCount
+
+/// 根据正文识别文件的语言，读取失败或识别结果不可靠时返回 `None`
+fn file_language(options: &CountOptions, f: &FileData) -> Option<String>
 {
-    filename: String,
-    word_count: u64
+    let text = match (&f.data, is_txt(&f.file))
+    {
+        (Some(data), true) => String::from_utf8_lossy(data).into_owned(),
+        (Some(data), false) => zip_text_sample(Cursor::new(data), &f.filename, options).ok()?,
+        (None, true) => String::from_utf8_lossy(&std::fs::read(&f.file).ok()?).into_owned(),
+        (None, false) => get_epub_text_sample(&f.file, options).ok()?
+    };
+    detect_language(&text)
 }
 
 
-pub fn get_all_epub_walkdir<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
-    fn is_epub(entry: &DirEntry) -> bool {
-        entry.file_type().is_file()
-            && entry
-            .path()
-            .extension()
-            .map_or(false, |ext| ext.eq_ignore_ascii_case("epub"))
+/// 统计 EPUB 中的图片数量，`.txt` 没有图片，返回 `None`
+fn file_images(options: &CountOptions, f: &FileData) -> Option<u64>
+{
+    if is_txt(&f.file)
+    {
+        return None;
     }
-
-    WalkDir::new(path)
-        .into_iter()
-        .filter_entry(|e| {
-            e.file_type().is_dir() || is_epub(e)
-        })
-        .filter_map(|e| e.ok())
-        .filter(is_epub)
-        .map(|e| e.into_path())
-        .collect()
+    match &f.data
+    {
+        Some(data) => zip_image_count(Cursor::new(data)),
+        None => get_epub_image_count(&f.file, options)
+    }.ok()
 }
 
 
-fn html_word_count(string: &String) -> u64
+/// 在工作线程中统计单个文件并组装结果
+fn process_file(args: &Cli, options: &CountOptions, cache: Option<&Cache>, f: FileData) -> Result<FileWordCount, FailedFile>
 {
-    Html::parse_document(string)
-        .root_element()
-        .text()
-        .collect::<String>()
-        .split_whitespace()
-        .collect::<Vec<_>>()
-        .join("")
-        .chars()
-        .count() as u64
-}
-
-
-fn zip_xhtml_read<W: Read + Seek>(file: W) -> Vec<String> {
-    let mut zip = ZipArchive::new(file).expect("读取zip文件时出现错误");
-
-    let n = zip.len();
-    let mut results = Vec::new();
-
-    for i in 0..n {
-        let mut file = zip.by_index(i).expect("遍历zip文件列表时出现错误");
-        let name = file.name();
-
-        if !(name.ends_with(".xhtml") || name.ends_with(".html")) {
-            continue;
+    let started = Instant::now();
+    let metadata = || {
+        let wants_creators = args.group_by_author || args.author.is_some();
+        let mut metadata: epub_count::opf::Metadata = if (args.show_title || args.detect_lang || args.count_metadata || wants_creators)
+            && !is_txt(&f.file)
+        {
+            match &f.data
+            {
+                Some(data) => zip_metadata_read_with(Cursor::new(data), options),
+                None => get_epub_metadata(&f.file, options)
+            }.ok().flatten().unwrap_or_default()
         }
-        if name == "toc.xhtml" || name == "toc.html" {
-            continue;
+        else
+        {
+            Default::default()
+        };
+        if !args.show_title
+        {
+            metadata.title = None;
+            if !wants_creators
+            {
+                metadata.creators.clear();
+            }
         }
+        if args.detect_lang && metadata.language.is_none()
+        {
+            metadata.language = file_language(options, &f);
+        }
+        metadata
+    };
+    let images = || args.count_images.then(|| file_images(options, &f)).flatten();
 
-        let size = file.size();
-        let mut content = String::with_capacity(size as usize);
-
-        file.read_to_string(&mut content).expect("读取xhtml文件时出现错误");
-        results.push(content);
+    if is_cbz(&f.file)
+    {
+        let pages = match &f.data
+        {
+            Some(data) => zip_image_count(Cursor::new(data)),
+            None => get_epub_image_count(&f.file, options)
+        };
+        return match pages
+        {
+            Ok(pages) => Ok(FileWordCount {
+                index: f.index,
+                path: shown_path(args, &f),
+                filename: f.filename,
+                pages: Some(pages),
+                elapsed: args.timing.then(|| started.elapsed()),
+                ..Default::default()
+            }),
+            Err(e) => Err(FailedFile {
+                index: f.index,
+                filename: f.filename,
+                error: format!("{:#}", e),
+                encrypted: false
+            })
+        };
     }
 
-    results
-}
-
-fn get_epub_word_count<P: AsRef<Path>>(path: P) -> u64
-{
-    let file = open_file(path);
-    let chars = zip_xhtml_read(file);
-    let word_count: u64 = chars.iter().map(
-        |s| html_word_count(s)
-    ).sum::<u64>();
+    if let Some(word_count) = cache.and_then(|c| c.get(&f.file))
+    {
+        let metadata = metadata();
+        let images = images();
+        return Ok(FileWordCount {
+            index: f.index,
+            path: shown_path(args, &f),
+            filename: f.filename,
+            word_count,
+            title: metadata.title,
+            creators: metadata.creators,
+            language: metadata.language,
+            images,
+            elapsed: args.timing.then(|| started.elapsed()),
+            ..Default::default()
+        });
+    }
 
-    word_count
+    let mut chapters = match count_file(options, &f) {
+        Ok(chapters) => chapters,
+        Err(e) => {
+            return Err(FailedFile {
+                index: f.index,
+                filename: f.filename,
+                error: format!("{:#}", e),
+                encrypted: e.downcast_ref::<Encrypted>().is_some()
+            });
+        }
+    };
+    let metadata = metadata();
+    let metadata_words = args.count_metadata.then(|| {
+        metadata.description.as_deref()
+            .map_or(0, |d| count_plain_text(String::new(), &html_text(d), options).word_count)
+    });
+    let word_count = chapters.iter().map(|c| c.word_count).sum::<u64>() + metadata_words.unwrap_or(0);
+    let segmented_words = options.segment.then(|| chapters.iter().filter_map(|c| c.segmented_words).sum());
+    let chapter_count = args.chapter_count.then_some(chapters.len() as u64);
+    let paragraphs = options.structure.then(|| chapters.iter().filter_map(|c| c.paragraphs).sum());
+    let sentences = options.structure.then(|| chapters.iter().filter_map(|c| c.sentences).sum());
+    let scripts = options.script_breakdown.then(|| {
+        let mut all = ScriptCounts::default();
+        for scripts in chapters.iter().filter_map(|c| c.scripts.as_ref())
+        {
+            all.add(scripts);
+        }
+        all
+    });
+    if !args.script_breakdown
+    {
+        // 只为 `--reading-speed-cjk` 统计的文字种类不在各章的结果中输出
+        chapters.iter_mut().for_each(|c| c.scripts = None);
+    }
+    let frequencies = options.collect_frequencies.then(|| {
+        let mut all = Frequencies::new();
+        for chapter in &mut chapters
+        {
+            merge_frequencies(&mut all, chapter.frequencies.take().unwrap_or_default());
+        }
+        all
+    });
+    let images = images();
+    Ok(FileWordCount{
+        index: f.index,
+        path: shown_path(args, &f),
+        filename: f.filename,
+        word_count,
+        title: metadata.title,
+        creators: metadata.creators,
+        segmented_words,
+        paragraphs,
+        sentences,
+        scripts,
+        chapter_count,
+        chapters: (args.by_chapter && !is_txt(&f.file)).then_some(chapters),
+        frequencies,
+        elapsed: args.timing.then(|| started.elapsed()),
+        language: metadata.language,
+        images,
+        metadata_words,
+        pages: None
+    })
 }
 
 
-fn split_vec<T>(mut vec: Vec<T>, n: usize) -> Vec<Vec<T>> {
-    if n == 0 || vec.is_empty() {
-        return vec![vec];
+fn main()
+{
+    let started = Instant::now();
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    match config::load(args.config.clone())
+    {
+        Ok(config) => config.apply(&mut args, &matches).unwrap_or_else(|e| e.exit()),
+        Err(e) => Cli::command().error(ErrorKind::Io, format!("{:#}", e)).exit()
     }
+    init_logger(args.verbose);
 
-    let len = vec.len();
-    let chunk_size = (len + n - 1) / n;
-    let mut result = Vec::new();
-
-    while !vec.is_empty() {
-        let take = chunk_size.min(vec.len());
-        let chunk = vec.drain(..take).collect::<Vec<T>>();
-        result.push(chunk);
+    let mut epub_renders: Vec<FileData> = Vec::new();
+    let mut missing_input = false;
+    let mut walk_options = WalkOptions {
+        max_depth: args.max_depth,
+        follow_symlinks: args.follow_symlinks,
+        parallel: args.parallel_walk,
+        use_ignore_file: !args.no_ignore,
+        modified_after: args.after.as_deref().map(time_arg),
+        modified_before: args.before.as_deref().map(time_arg),
+        ..Default::default()
+    };
+    if !args.ext.is_empty()
+    {
+        walk_options.extensions = args.ext.clone();
+    }
+    if args.include_txt
+    {
+        walk_options.extensions.push("txt".to_string());
+    }
+    if !args.exclude.is_empty()
+    {
+        match ExcludeSet::new(&args.exclude)
+        {
+            Ok(set) => walk_options.exclude = Some(set),
+            Err(e) => Cli::command().error(ErrorKind::ValueValidation, format!("{:#}", e)).exit()
+        }
     }
 
-    result
-}
-
-
-fn open_file<P: AsRef<Path>>(p: P) -> Box<dyn ReadSeek>
-{
-    let file = OpenOptions::new()
-        .read(true)
-        .write(false)
-        .create(false)
-        .open(p)
-        .expect("打开文件失败");
-    let file_mmap = unsafe { Mmap::map(&file) };
-    match file_mmap {
-        Ok(mmap) => Box::new(Cursor::new(mmap)),
-        Err(_) => {
-            Box::new(file)
+    if args.watch
+    {
+        if args.files.iter().any(|f| f == "-")
+        {
+            Cli::command().error(ErrorKind::ArgumentConflict, "--watch 不能与从标准输入读取路径同时使用").exit()
+        }
+        if let Err(e) = watch::run(&args.files, args.walk, &walk_options.extensions)
+        {
+            eprintln!("{:#}", e);
         }
+        exit(EXIT_PARTIAL_FAILURE)
     }
-}
 
+    // 全局线程池要等知道文件数（`--threads-auto-cap`）后才创建，并行遍历使用临时的线程池，
+    // 遍历结束即释放，不会与统计用的线程同时存在
+    let walk_pool = (args.walk && args.parallel_walk).then(|| {
+        ThreadPoolBuilder::new()
+            .num_threads(args.cpu_nums)
+            .build()
+            .expect("创建线程池时出现错误")
+    });
 
-fn main()
-{
-    let args = Cli::parse();
+    let inputs = if args.files.len() == 1 && args.files[0] == "-"
+    {
+        read_stdin_paths(args.null)
+    }
+    else
+    {
+        args.files.clone()
+    };
 
-    let mut epub_renders: Vec<FileData> = Vec::new();
+    for file in &inputs {
+        if file.starts_with("http://") || file.starts_with("https://")
+        {
+            match fetch_url(file, Duration::from_secs(args.timeout), args.max_download.saturating_mul(1024 * 1024))
+            {
+                Ok((filename, data)) => epub_renders.push(FileData {
+                    index: epub_renders.len(),
+                    filename,
+                    file: PathBuf::from(file.as_str()),
+                    data: Some(data),
+                    root: None,
+                    member: None
+                }),
+                Err(e) => {
+                    eprintln!("下载 {} 时出现错误：{:#}", file, e);
+                    missing_input = true;
+                }
+            }
+            continue;
+        }
 
-    for file in &args.files {
         let path = PathBuf::from(file.as_str());
 
         if !path.exists() {
             eprintln!("文件/目录 {} 不存在", file);
+            missing_input = true;
             continue;
         }
 
         if args.walk && path.is_dir()
         {
-            for p in get_all_epub_walkdir(path.clone()) {
+            let books = match &walk_pool
+            {
+                Some(pool) => pool.install(|| get_all_books_walkdir(&path, &walk_options)),
+                None => get_all_books_walkdir(&path, &walk_options)
+            };
+            for p in books {
                 let s = FileData {
-                    filename: p.file_name().unwrap().to_str().unwrap().to_string(),
-                    file: p
+                    index: epub_renders.len(),
+                    filename: p.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                    file: p,
+                    data: None,
+                    root: Some(path.clone()),
+                    member: None
                 };
                 epub_renders.push(s);
             }
@@ -209,69 +2096,738 @@ fn main()
         {
             continue;
         }
+        else if path.is_file() && is_tar(&path)
+        {
+            match tar_members(&path, &walk_options.extensions, args.dedup)
+            {
+                Ok(members) => {
+                    for (ordinal, member) in members.into_iter().enumerate()
+                    {
+                        let file = path.join(&member.name);
+                        epub_renders.push(FileData {
+                            index: epub_renders.len(),
+                            filename: file.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                            file,
+                            data: None,
+                            root: None,
+                            member: Some(TarMember {
+                                archive: path.clone(),
+                                ordinal,
+                                size: member.size,
+                                digest: member.digest
+                            })
+                        });
+                    }
+                }
+                Err(e) => {
+                    eprintln!("读取 {} 时出现错误：{:#}", file, e);
+                    missing_input = true;
+                }
+            }
+        }
         else if path.is_file()
         {
             let s = FileData {
-                filename: path.file_name().unwrap().to_str().unwrap().to_string(),
-                file: path
+                index: epub_renders.len(),
+                filename: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                file: path,
+                data: None,
+                root: None,
+                member: None
             };
             epub_renders.push(s);
         }
         else
         {
-            panic!("未知输入")
+            // FIFO、设备文件等既不是普通文件也不是目录
+            eprintln!("{} 不是普通文件或目录，已跳过", file);
+            missing_input = true;
         }
     }
 
+    drop(walk_pool);
+
     if epub_renders.is_empty()
     {
         eprintln!("没有找到任何EPUB文件");
-        exit(0)
+        exit(EXIT_NO_INPUT)
+    }
+
+    if args.dedup
+    {
+        let before = epub_renders.len();
+        epub_renders = dedup(epub_renders);
+        let skipped = before - epub_renders.len();
+        if skipped > 0
+        {
+            eprintln!("已跳过 {} 个重复文件", skipped);
+        }
+    }
+
+    let discovered = epub_renders.len();
+    if let Some(n) = args.sample.filter(|&n| n < discovered)
+    {
+        let seed = args.seed.unwrap_or_else(rand::random);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        epub_renders.shuffle(&mut rng);
+        epub_renders.truncate(n);
+        // 抽中的文件仍按原顺序统计，序号重新编排以便与 `paths` 对应
+        epub_renders.sort_by_key(|f| f.index);
+        for (i, f) in epub_renders.iter_mut().enumerate()
+        {
+            f.index = i;
+        }
+        eprintln!("从 {} 个文件中抽样 {} 个（种子 {}）", discovered, n, seed);
+    }
+
+    let sampled = epub_renders.len();
+    let has_cbz = epub_renders.iter().any(|f| is_cbz(&f.file));
+
+    if args.dry_run
+    {
+        for f in &epub_renders
+        {
+            println!("{}", f.file.display());
+        }
+        println!("共 {} 个文件", epub_renders.len());
+        exit(if missing_input { EXIT_PARTIAL_FAILURE } else { 0 })
+    }
+
+
+    let mut out: Box<dyn Write> = match &args.output
+    {
+        Some(path) => match OpenOptions::new().write(true).create(true).append(args.append).truncate(!args.append).open(path)
+        {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => Cli::command()
+                .error(ErrorKind::Io, format!("无法打开输出文件 {}：{}", path.display(), e))
+                .exit()
+        },
+        None => Box::new(stdout())
+    };
+    macro_rules! emit {
+        ($($arg:tt)*) => {
+            if let Err(e) = writeln!(out, $($arg)*)
+            {
+                eprintln!("写入输出时出现错误：{}", e);
+                exit(EXIT_PARTIAL_FAILURE)
+            }
+        };
     }
 
 
+    // 需要排序时只能等全部结果到齐，流式输出退化为统一输出
+    // ndjson 总是边统计边输出，文本格式需要 `--stream-output`
+    let stream_output = ((args.stream_output && args.format == OutputFormat::Text) || args.format == OutputFormat::Ndjson)
+        && !args.quiet
+        && args.sort.is_none()
+        && !args.group_by_dir
+        && !args.group_by_author
+        && args.author.is_none()
+        && args.diff.is_none()
+        && !args.percent;
+    let options = count_options(&args);
+    // 第一次 Ctrl-C 只停止分派，等正在统计的文件完成后输出部分结果；第二次直接退出
+    if let Err(e) = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst)
+        {
+            exit(EXIT_INTERRUPTED)
+        }
+    })
+    {
+        eprintln!("警告：注册 Ctrl-C 处理函数失败：{}", e);
+    }
+
+    let paths: Vec<PathBuf> = epub_renders.iter().map(|f| f.file.clone()).collect();
+    // 缓存中的字数只对默认的计数方式有效；大小上限、密码与 `--strict` 决定了哪些文件能统计成功，同样必须是默认值
+    let defaults = CountOptions::default();
+    let cache_path = args.cache.clone()
+        .filter(|_| {
+            args.count_mode == CountModeArg::Chars
+                && args.count == CountBy::Chars
+                && !args.keep_ruby
+                && args.chapter_filter.is_none()
+                && args.encoding.is_none()
+                && args.content_selector.is_none()
+                && !args.exclude_front_matter
+                && !args.include_head
+                && !args.include_punctuation
+                && args.chapters.is_none()
+                && !args.count_metadata
+                && args.password.is_none()
+                && !args.strict
+                && options.max_entry_size == defaults.max_entry_size
+                && options.max_total_size == defaults.max_total_size
+        });
+    let mut cache = cache_path.map(|path| {
+        Cache::load(path.clone()).unwrap_or_else(|e| {
+            eprintln!("警告：{:#}，将重新统计所有文件", e);
+            Cache::empty(path)
+        })
+    });
+    // 缓存只记录总字数，需要分章、词频或分词结果时不能直接使用
+    let cache_hits = cache.as_ref()
+        .filter(|_| !args.by_chapter && !args.unique && args.top_words.is_none() && !args.segment && !args.structure && !args.chapter_count
+            && !args.script_breakdown && args.reading_speed_cjk.is_none());
 
-    let mut total_word_count: u64 = 0;
-    let mut threads: Vec<JoinHandle<Vec<FileWordCount>>> = Vec::new();
-    for files in split_vec(epub_renders, args.cpu_nums)
+    let threads = if args.threads_auto_cap
+    {
+        args.cpu_nums.min(epub_renders.len()).max(1)
+    }
+    else
+    {
+        args.cpu_nums
+    };
+    // 文件级与章节级的并行都在同一个全局线程池中进行，总线程数不超过 `threads`
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .expect("创建线程池时出现错误");
+    let progress = if args.no_progress || !stdout().is_terminal()
     {
-        threads.push(thread::spawn(move || {
-            let mut infos: Vec<FileWordCount> = Vec::new();
-            for f in files
+        ProgressBar::hidden()
+    }
+    else
+    {
+        ProgressBar::with_draw_target(Some(epub_renders.len() as u64), ProgressDrawTarget::stderr())
+            .with_style(
+                ProgressStyle::with_template("{bar:40} {pos}/{len} 预计剩余 {eta}")
+                    .expect("进度条模板无效")
+            )
+    };
+    let mut infos: Vec<FileWordCount> = Vec::new();
+    let mut failed: Vec<FailedFile> = Vec::new();
+    let memory = args.max_memory.map(|mb| MemoryLimit::new(mb.saturating_mul(1024 * 1024)));
+    // 没有 --max-memory 时，最多同时有 2 × 线程数 个读出的 tar 成员等待统计
+    let tar_slots = MemoryLimit::new(2 * threads as u64);
+    // 工作线程统计完一个文件就累加，`--live-total` 时主线程定期打印
+    let live_total = AtomicU64::new(0);
+    let file_total = epub_renders.len();
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        let (args_ref, options_ref, memory_ref, live_total_ref) = (&args, &options, &memory, &live_total);
+        let (tar_slots_ref, extensions) = (&tar_slots, &walk_options.extensions);
+        // 在分派线程上申请内存配额、读出 tar 成员，工作线程不会因等待配额而阻塞
+        scope.spawn(move || rayon::in_place_scope(|s| {
+            let stopped = || INTERRUPTED.load(Ordering::SeqCst) || ABORTED.load(Ordering::SeqCst);
+            let dispatch = |f: FileData, permit| {
+                let tx = tx.clone();
+                s.spawn(move |_| {
+                    if stopped()
+                    {
+                        return;
+                    }
+                    let result = process_file(args_ref, options_ref, cache_hits, f);
+                    drop(permit);
+                    if let Ok(info) = &result
+                    {
+                        live_total_ref.fetch_add(info.word_count, Ordering::Relaxed);
+                    }
+                    if args_ref.fail_fast && result.is_err()
+                    {
+                        ABORTED.store(true, Ordering::SeqCst);
+                    }
+                    // 接收端在主线程退出或 `--fail-fast` 中止时关闭，此时结果已无人需要
+                    let _ = tx.send(result);
+                });
+            };
+
+            let mut files = epub_renders.into_iter().peekable();
+            while let Some(f) = files.next()
             {
-                let word_count = get_epub_word_count(f.file);
-                let info = FileWordCount{
-                    filename: f.filename,
-                    word_count
+                if stopped()
+                {
+                    break;
+                }
+                let Some(archive) = f.member.as_ref().map(|m| m.archive.clone()) else {
+                    let permit = memory_ref.as_ref().map(|m| m.acquire(file_size(&f)));
+                    dispatch(f, permit);
+                    continue;
+                };
+
+                // 同一个 tar 中相邻的成员在一次顺序读取中读出，读出一个就分派一个
+                let mut group = vec![f];
+                while let Some(next) = files.next_if(|n| n.member.as_ref().is_some_and(|m| m.archive == archive))
+                {
+                    group.push(next);
+                }
+                let wanted: Vec<usize> = group.iter().filter_map(|f| f.member.as_ref()).map(|m| m.ordinal).collect();
+                let mut group = group.into_iter().peekable();
+                let read = read_tar_members(&archive, extensions, &wanted, |_, size, entry| {
+                    if stopped()
+                    {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                    let permit = match memory_ref
+                    {
+                        Some(m) => m.acquire(size),
+                        None => tar_slots_ref.acquire(1)
+                    };
+                    let mut data = Vec::with_capacity(size as usize);
+                    entry.read_to_end(&mut data)?;
+                    let mut f = group.next().expect("读出的 tar 成员多于列出的成员");
+                    f.data = Some(data);
+                    dispatch(f, Some(permit));
+                    Ok(ControlFlow::Continue(()))
+                });
+                // 读取中途出错或 tar 在列出成员后被修改，剩下的成员都记为失败
+                let error = match read
+                {
+                    Err(e) => format!("读取 {} 时出现错误：{:#}", archive.display(), e),
+                    Ok(()) => format!("{} 中找不到该成员，文件可能已被修改", archive.display())
                 };
-                if args.stream_output
+                if stopped() || group.peek().is_none()
+                {
+                    continue;
+                }
+                if args_ref.fail_fast
+                {
+                    ABORTED.store(true, Ordering::SeqCst);
+                }
+                for f in group
                 {
-                    println!("{} 字数：{} 字", info.filename, info.word_count);
+                    let _ = tx.send(Err(FailedFile { index: f.index, filename: f.filename, error: error.clone(), encrypted: false }));
+                }
+            }
+        }));
+
+        // 结果在统计完成后立即送达，流式输出时边收边打印
+        for (received, result) in rx.into_iter().enumerate()
+        {
+            progress.inc(1);
+            if args.live_total.is_some_and(|n| (received + 1) % n == 0)
+            {
+                let total = live_total.load(Ordering::Relaxed);
+                progress.suspend(|| eprintln!(
+                    "已统计 {}/{} 个文件，累计 {}", received + 1, file_total, format_amount(&args, total)
+                ));
+            }
+            match result
+            {
+                Ok(info) => {
+                    if stream_output && in_word_range(&args, info.word_count)
+                    {
+                        let line = match args.format
+                        {
+                            OutputFormat::Ndjson => json_file(&args, &info, None, has_cbz).to_string(),
+                            _ => file_text(&args, &info, None, has_cbz)
+                        };
+                        progress.suspend(|| emit!("{}", line));
+                    }
+                    infos.push(info)
+                }
+                Err(f) if args.fail_fast => {
+                    failed.push(f);
+                    break;
+                }
+                Err(f) => {
+                    progress.suspend(|| eprintln!("警告：{} 统计失败，已跳过：{}", f.filename, f.error));
+                    failed.push(f)
                 }
-                infos.push(info);
             }
-            infos
-        }))
+        }
+    });
+    progress.finish_and_clear();
+    if let Some(f) = failed.first().filter(|_| args.fail_fast)
+    {
+        let _ = out.flush();
+        fail_fast_exit(f)
+    }
+    let interrupted = INTERRUPTED.load(Ordering::SeqCst);
+    if interrupted
+    {
+        eprintln!("已中断：共统计了 {} 个文件，以下为部分结果", infos.len() + failed.len());
+    }
+
+    failed.sort_by_key(|f| f.index);
+    // 结果顺序与线程调度无关，始终与输入/遍历顺序一致
+    infos.sort_by_key(|info| info.index);
+    let empty: Vec<String> = infos.iter()
+        .filter(|info| args.warn_empty && info.word_count == 0)
+        .map(|info| shown_name(info).to_string())
+        .collect();
+    match args.sort
+    {
+        Some(SortOrder::Name) => infos.sort_by(|a, b| shown_name(a).cmp(shown_name(b))),
+        Some(SortOrder::CountAsc) => infos.sort_by(|a, b| {
+            a.word_count.cmp(&b.word_count).then_with(|| shown_name(a).cmp(shown_name(b)))
+        }),
+        Some(SortOrder::CountDesc) => infos.sort_by(|a, b| {
+            b.word_count.cmp(&a.word_count).then_with(|| shown_name(a).cmp(shown_name(b)))
+        }),
+        None => {}
+    }
+    if let Some(cache) = &mut cache
+    {
+        for info in infos.iter().filter(|info| info.pages.is_none())
+        {
+            cache.insert(&paths[info.index], info.word_count);
+        }
+        if let Err(e) = cache.save()
+        {
+            eprintln!("警告：{:#}", e);
+        }
     }
 
-    if !args.stream_output
+    retain_author(&args, &mut infos);
+
+    if let Some(other) = &args.diff
     {
-        for handle in threads {
-            let infos = handle.join().unwrap();
-            for info in infos {
-                println!("{} 字数：{} 字", info.filename, info.word_count);
-                total_word_count += info.word_count;
+        let files = if other.is_dir() { get_all_books_walkdir(other, &walk_options) } else { vec![other.clone()] };
+        let others: Vec<FileData> = files.into_iter()
+            .enumerate()
+            .map(|(index, file)| FileData {
+                index,
+                filename: file.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                file,
+                data: None,
+                root: other.is_dir().then(|| other.clone()),
+                member: None
+            })
+            .collect();
+        let results: Vec<_> = others.into_par_iter().map(|f| process_file(&args, &options, None, f)).collect();
+        let mut other_infos = Vec::new();
+        for result in results
+        {
+            match result
+            {
+                Ok(info) => other_infos.push(info),
+                Err(f) if args.fail_fast => fail_fast_exit(&f),
+                Err(f) => {
+                    eprintln!("警告：{} 统计失败，已跳过：{}", f.filename, f.error);
+                    failed.push(f)
+                }
             }
         }
+        retain_author(&args, &mut other_infos);
+        infos.retain(|info| in_word_range(&args, info.word_count));
+        other_infos.retain(|info| in_word_range(&args, info.word_count));
+        emit!("{}", diff_report(&args, other, &diff(&infos, &other_infos, args.show_title)));
     }
     else
     {
-        for handle in threads
+        let counted = infos.len();
+        let total = (!args.filtered_total).then(|| sum_infos(&infos));
+        infos.retain(|info| in_word_range(&args, info.word_count));
+        let total = total.unwrap_or_else(|| sum_infos(&infos));
+        let total_word_count = total.word_count;
+        let grand_total = Some(total_word_count);
+        // 抽样时按文件数比例推算全部文件的总字数
+        let estimate = (sampled < discovered && counted > 0)
+            .then(|| (total_word_count as f64 * discovered as f64 / counted as f64).round() as u64);
+        let estimate_text = |estimate: u64| {
+            format!("估算总字数：{}（按 {}/{} 个文件推算）", format_amount(&args, estimate), counted, discovered)
+        };
+
+        match args.format
+        {
+            OutputFormat::Text => {
+                if let Some(groups) = output_groups(&args, &infos, &paths)
+                {
+                    for (name, group) in groups
+                    {
+                        emit!("{}：", name);
+                        if !args.quiet
+                        {
+                            for info in &group
+                            {
+                                emit!("  {}", file_text(&args, info, grand_total, has_cbz).replace('\n', "\n  "));
+                            }
+                        }
+                        let subtotal = sum_infos(group);
+                        emit!("  {}", text_line(&args, "小计", subtotal.word_count, &columns(&args, &subtotal, grand_total, has_cbz)));
+                    }
+                }
+                else if !stream_output && !args.quiet
+                {
+                    for info in &infos
+                    {
+                        emit!("{}", file_text(&args, info, grand_total, has_cbz));
+                    }
+                }
+                emit!("{}", text_line(&args, "总字数", total_word_count, &columns(&args, &total, None, has_cbz)));
+                if let Some(estimate) = estimate
+                {
+                    emit!("{}", estimate_text(estimate))
+                }
+                if let Some(stats) = args.stats.then(|| stats(&infos)).flatten()
+                {
+                    emit!("{}", stats_text(&args, &stats))
+                }
+                if args.histogram
+                {
+                    emit!("{}", histogram_text(&args, &histogram(&args, &infos)))
+                }
+            }
+            OutputFormat::Table | OutputFormat::Markdown => {
+                if args.format == OutputFormat::Table
+                {
+                    emit!("{}", table(&args, &infos, &total, has_cbz));
+                }
+                else
+                {
+                    // 表格后紧接的文字会被当作表格的一行，需要空一行隔开
+                    let blank = if args.stats || args.histogram || estimate.is_some() { "\n" } else { "" };
+                    emit!("{}{}", markdown(&args, &infos, &total, has_cbz), blank);
+                }
+                if let Some(estimate) = estimate
+                {
+                    emit!("{}", estimate_text(estimate))
+                }
+                if let Some(stats) = args.stats.then(|| stats(&infos)).flatten()
+                {
+                    emit!("{}", stats_text(&args, &stats))
+                }
+                if args.histogram
+                {
+                    let histogram = histogram_text(&args, &histogram(&args, &infos));
+                    if args.format == OutputFormat::Markdown
+                    {
+                        // 条形图需要等宽字体，放进代码块
+                        emit!("```\n{}\n```", histogram)
+                    }
+                    else
+                    {
+                        emit!("{}", histogram)
+                    }
+                }
+            }
+            OutputFormat::Html => {
+                let mut footer: Vec<String> = estimate.map(estimate_text).into_iter().collect();
+                if let Some(stats) = args.stats.then(|| stats(&infos)).flatten()
+                {
+                    footer.extend(stats_text(&args, &stats).lines().map(str::to_string));
+                }
+                if args.histogram
+                {
+                    footer.extend(histogram_text(&args, &histogram(&args, &infos)).lines().map(str::to_string));
+                }
+                emit!("{}", html_report(&args, &infos, &total, has_cbz, &footer))
+            }
+            OutputFormat::Json | OutputFormat::Ndjson => {
+                let mut output = Map::new();
+                if args.format == OutputFormat::Ndjson
+                {
+                    if !stream_output && !args.quiet
+                    {
+                        for info in &infos
+                        {
+                            emit!("{}", json_file(&args, info, grand_total, has_cbz));
+                        }
+                    }
+                }
+                else if !args.quiet
+                {
+                    let files: Vec<Value> = infos.iter()
+                        .map(|info| json_file(&args, info, grand_total, has_cbz))
+                        .collect();
+                    output.insert("files".to_string(), Value::Array(files));
+                }
+                if !failed.is_empty()
+                {
+                    let failed: Vec<Value> = failed.iter()
+                        .map(|f| json!({
+                            "filename": f.filename,
+                            "status": if f.encrypted { "encrypted" } else { "error" },
+                            "error": f.error
+                        }))
+                        .collect();
+                    output.insert("failed".to_string(), Value::Array(failed));
+                }
+                output.insert("total".to_string(), json!(total_word_count));
+                if let Some(estimate) = estimate
+                {
+                    output.insert("estimated_total".to_string(), json!(estimate));
+                }
+                for (key, value) in json_columns(&args, &total, None, has_cbz).into_iter().filter(|(_, v)| !v.is_null())
+                {
+                    output.insert(format!("total_{}", key), value);
+                }
+                if let Some(groups) = output_groups(&args, &infos, &paths)
+                {
+                    let (key, field) = if args.group_by_author { ("authors", "author") } else { ("directories", "directory") };
+                    let groups: Vec<Value> = groups.into_iter()
+                        .map(|(name, group)| {
+                            let mut entry = Map::new();
+                            entry.insert(field.to_string(), json!(name));
+                            entry.insert("files".to_string(), json!(group.len()));
+                            entry.insert("total".to_string(), json!(sum_infos(group).word_count));
+                            Value::Object(entry)
+                        })
+                        .collect();
+                    output.insert(key.to_string(), Value::Array(groups));
+                }
+                if let Some(stats) = args.stats.then(|| stats(&infos)).flatten()
+                {
+                    output.insert("stats".to_string(), stats_json(&stats));
+                }
+                if args.histogram
+                {
+                    output.insert("histogram".to_string(), histogram_json(&histogram(&args, &infos)));
+                }
+                emit!("{}", json_text(&args, &Value::Object(output)))
+            }
+            OutputFormat::Csv => {
+                let header: Vec<&str> = columns(&args, &total, grand_total, has_cbz).iter().map(|c| c.key).collect();
+                let row = |name: &str, info: &FileWordCount| {
+                    let mut fields = vec![csv_escape(name), info.word_count.to_string()];
+                    fields.extend(columns(&args, info, grand_total, has_cbz).iter().map(|c| csv_escape(&c.display())));
+                    fields.join(",")
+                };
+                emit!("{}", ["filename", "word_count"].iter().chain(&header).copied().collect::<Vec<_>>().join(","));
+                if !args.quiet
+                {
+                    for info in &infos
+                    {
+                        emit!("{}", row(shown_name(info), info));
+                    }
+                }
+                emit!("{}", row("TOTAL", &total))
+            }
+        }
+    }
+
+    if let Err(e) = out.flush()
+    {
+        eprintln!("写入输出时出现错误：{}", e);
+        exit(EXIT_PARTIAL_FAILURE)
+    }
+    if args.timing
+    {
+        eprintln!("总耗时：{:.2} s", started.elapsed().as_secs_f64());
+    }
+
+    if !failed.is_empty()
+    {
+        eprintln!("{} 个文件统计失败：", failed.len());
+        for f in &failed
+        {
+            eprintln!("  {}：{}", f.filename, f.error);
+        }
+    }
+    if !empty.is_empty()
+    {
+        eprintln!("警告：{} 个文件字数为 0，可能只有图片或解析失败：", empty.len());
+        for name in &empty
         {
-            let infos = handle.join().unwrap();
-            total_word_count += infos.iter().map(|info| info.word_count).sum::<u64>();
+            eprintln!("  {}", name);
         }
     }
+    if interrupted
+    {
+        exit(EXIT_INTERRUPTED)
+    }
+    if missing_input || failed.iter().any(|f| !f.encrypted) || (args.strict && !empty.is_empty())
+    {
+        exit(EXIT_PARTIAL_FAILURE)
+    }
+    if !failed.is_empty()
+    {
+        exit(EXIT_ENCRYPTED)
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parse_time_rfc3339()
+    {
+        let time = parse_time("2020-01-01T00:00:00Z").unwrap();
+        assert_eq!(time.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(), 1577836800);
+        let offset = parse_time("2020-01-01T08:00:00+08:00").unwrap();
+        assert_eq!(offset, time);
+    }
+
+    #[test]
+    fn parse_time_naive()
+    {
+        let date = parse_time("2020-01-01").unwrap();
+        assert_eq!(parse_time("2020-01-01 00:00"), Some(date));
+        assert_eq!(parse_time("2020-01-01T00:00:00"), Some(date));
+        assert!(parse_time("2020-01-01 12:30:00").unwrap() > date);
+    }
+
+    #[test]
+    fn parse_time_relative()
+    {
+        let week = parse_time("7d").unwrap();
+        let now = SystemTime::now();
+        let ago = now.duration_since(week).unwrap().as_secs();
+        assert!((7 * 24 * 60 * 60..7 * 24 * 60 * 60 + 60).contains(&ago));
+        assert!(parse_time("12h").unwrap() > week);
+        assert_eq!(parse_time("1w").map(|t| t <= now), Some(true));
+    }
+
+    #[test]
+    fn parse_time_rejects_garbage()
+    {
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("d"), None);
+        assert_eq!(parse_time("7y"), None);
+        assert_eq!(parse_time("7天"), None);
+        assert_eq!(parse_time("2020-13-01"), None);
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn reading_time_splits_cjk_and_latin()
+    {
+        assert_eq!(format_reading_time(600, 0, 250, 500), "0h 3m");
+        // 500 个汉字与 250 个词各需 1 分钟
+        assert_eq!(format_reading_time(750, 500, 250, 500), "0h 2m");
+        assert_eq!(format_reading_time(750, 500, 250, 250), "0h 3m");
+        assert_eq!(format_reading_time(90000, 90000, 250, 500), "3h 0m");
+    }
+
+    #[test]
+    fn json_scripts_are_nested_at_both_levels()
+    {
+        let args = Cli::parse_from(["epub-count", "--script-breakdown", "--by-chapter", "a.epub"]);
+        let scripts = ScriptCounts { cjk: 2, latin: 5, digits: 1, other: 1 };
+        let info = FileWordCount {
+            filename: "a.epub".to_string(),
+            word_count: 4,
+            scripts: Some(scripts),
+            chapters: Some(vec![ChapterWordCount {
+                name: "ch1.xhtml".to_string(),
+                word_count: 4,
+                frequencies: None,
+                segmented_words: None,
+                paragraphs: None,
+                sentences: None,
+                scripts: Some(scripts)
+            }]),
+            ..FileWordCount::default()
+        };
+        let json = json_file(&args, &info, None, false);
+        let expected = json!({"cjk": 2, "latin": 5, "digits": 1, "other": 1});
+        assert_eq!(json["scripts"], expected);
+        assert_eq!(json["chapters"][0]["scripts"], expected);
+        assert!(json.as_object().unwrap().keys().all(|k| !k.starts_with("script_")));
+    }
+
+    #[test]
+    fn author_filter_applies_to_both_diff_sides()
+    {
+        let args = Cli::parse_from(["epub-count", "--author", "甲", "--diff", "old", "new"]);
+        let book = |filename: &str, author: &str| FileWordCount {
+            filename: filename.to_string(),
+            creators: vec![author.to_string()],
+            ..FileWordCount::default()
+        };
+        let mut left = vec![book("a.epub", "甲"), book("b.epub", "乙")];
+        let mut right = vec![book("a.epub", "甲"), book("b.epub", "乙"), book("c.epub", "乙")];
+        retain_author(&args, &mut left);
+        retain_author(&args, &mut right);
 
-    println!("总字数：{} 字", total_word_count)
+        let result = diff(&left, &right, false);
+        assert_eq!(result.matched.len(), 1);
+        assert_eq!(result.matched[0].0.filename, "a.epub");
+        assert!(result.only_left.is_empty());
+        assert!(result.only_right.is_empty());
+    }
 }
@@ -0,0 +1,53 @@
+//! `--max-memory` 使用的内存配额
+//!
+//! 分派文件前按文件大小申请配额，配额不足时等待其他文件统计完成后归还。
+//! 单个文件超过总配额时按总配额计算，保证它最终仍能单独统计。
+
+use std::sync::{Condvar, Mutex};
+
+
+pub struct MemoryLimit
+{
+    limit: u64,
+    used: Mutex<u64>,
+    freed: Condvar
+}
+
+
+/// 持有期间占用配额，释放时归还
+pub struct Permit<'a>
+{
+    owner: &'a MemoryLimit,
+    bytes: u64
+}
+
+
+impl MemoryLimit
+{
+    pub fn new(limit: u64) -> MemoryLimit
+    {
+        MemoryLimit { limit, used: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    /// 申请 `bytes` 字节的配额，不足时阻塞到有足够的配额为止
+    pub fn acquire(&self, bytes: u64) -> Permit<'_>
+    {
+        let bytes = bytes.min(self.limit);
+        let mut used = self.used.lock().unwrap();
+        while *used + bytes > self.limit {
+            used = self.freed.wait(used).unwrap();
+        }
+        *used += bytes;
+        Permit { owner: self, bytes }
+    }
+}
+
+
+impl Drop for Permit<'_>
+{
+    fn drop(&mut self)
+    {
+        *self.owner.used.lock().unwrap() -= self.bytes;
+        self.owner.freed.notify_all();
+    }
+}
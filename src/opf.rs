@@ -0,0 +1,287 @@
+//! OPF（Open Packaging Format）相关的解析
+//!
+//! 通过 `META-INF/container.xml` 找到 OPF 文件，再从中读取 manifest 与 spine。
+
+use anyhow::{Context, Result};
+use roxmltree::{Document, Node};
+
+
+pub const CONTAINER_PATH: &str = "META-INF/container.xml";
+pub const ENCRYPTION_PATH: &str = "META-INF/encryption.xml";
+
+/// `--exclude-front-matter` 排除的 guide/landmarks 类型
+///
+/// EPUB2 guide 的 `type` 与 EPUB3 landmarks 的 `epub:type` 写法略有不同，两种都列出。
+const FRONT_MATTER_TYPES: [&str; 13] = [
+    "cover", "title-page", "titlepage", "copyright-page", "toc", "loi", "lot", "dedication",
+    "acknowledgements", "acknowledgments", "colophon", "imprint", "frontmatter"
+];
+
+/// 只用于混淆嵌入字体的算法（IDPF 与 Adobe），不影响正文
+const FONT_OBFUSCATION: [&str; 2] = ["http://www.idpf.org/2008/embedding", "http://ns.adobe.com/pdf/enc#RC"];
+
+
+/// 解析 OPF 后得到的书籍结构
+pub struct Package
+{
+    /// 按 spine 阅读顺序排列的内容文档在 zip 中的完整路径
+    pub spine: Vec<String>,
+    /// 导航文档（EPUB3 `properties="nav"` 与 EPUB2 的 NCX），不计入字数
+    pub navigation: Vec<String>,
+    /// EPUB2 `<guide>` 中指向封面、版权页、目录等前后附文的文档
+    pub front_matter: Vec<String>,
+    pub metadata: Metadata
+}
+
+
+/// OPF `<metadata>` 中的 Dublin Core 信息
+#[derive(Default)]
+pub struct Metadata
+{
+    /// 第一个 `<dc:title>`
+    pub title: Option<String>,
+    /// 所有 `<dc:creator>`，按出现顺序
+    pub creators: Vec<String>,
+    /// 第一个 `<dc:language>`，通常是 BCP 47 标签，如 `zh-CN`
+    pub language: Option<String>,
+    /// 第一个 `<dc:description>`（简介），常见为转义后的 HTML
+    pub description: Option<String>
+}
+
+impl Package
+{
+    pub fn is_navigation(&self, path: &str) -> bool
+    {
+        self.navigation.iter().any(|p| p == path)
+    }
+}
+
+
+/// manifest 中的一项
+struct ManifestItem<'a>
+{
+    id: &'a str,
+    path: String,
+    media_type: &'a str,
+    properties: &'a str
+}
+
+impl ManifestItem<'_>
+{
+    fn is_content_document(&self) -> bool
+    {
+        matches!(self.media_type, "application/xhtml+xml" | "text/html")
+    }
+
+    fn is_nav(&self) -> bool
+    {
+        self.properties.split_whitespace().any(|p| p == "nav")
+    }
+
+    fn is_ncx(&self) -> bool
+    {
+        self.media_type == "application/x-dtbncx+xml"
+    }
+}
+
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>>
+{
+    node.children().find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+
+/// 从 `container.xml` 中取出第一个 rootfile 的路径
+pub fn find_rootfile(container: &str) -> Result<String>
+{
+    let doc = Document::parse(container).context("解析container.xml时出现错误")?;
+    doc.descendants()
+        .find(|n| n.tag_name().name() == "rootfile")
+        .and_then(|n| n.attribute("full-path"))
+        .map(|p| p.to_string())
+        .context("container.xml中没有找到rootfile")
+}
+
+
+/// 从 `encryption.xml` 中取出被真正加密（而不只是字体混淆）的资源路径
+pub fn encrypted_resources(encryption: &str) -> Result<Vec<String>>
+{
+    let doc = Document::parse(encryption).context("解析encryption.xml时出现错误")?;
+    Ok(doc.descendants()
+        .filter(|n| n.tag_name().name() == "EncryptedData")
+        .filter(|n| {
+            let algorithm = child(*n, "EncryptionMethod").and_then(|m| m.attribute("Algorithm"));
+            !algorithm.is_some_and(|a| FONT_OBFUSCATION.contains(&a))
+        })
+        .filter_map(|n| n.descendants().find(|c| c.tag_name().name() == "CipherReference"))
+        .filter_map(|n| n.attribute("URI"))
+        .map(|uri| resolve_href("", uri))
+        .collect())
+}
+
+
+/// 解析 OPF 文件，`opf_path` 用于把 manifest 中的相对 href 解析为 zip 内路径
+pub fn parse_package(opf: &str, opf_path: &str) -> Result<Package>
+{
+    let doc = Document::parse(opf).context("解析OPF文件时出现错误")?;
+    let root = doc.root_element();
+    let base = match opf_path.rfind('/') {
+        Some(i) => &opf_path[..=i],
+        None => ""
+    };
+
+    let manifest: Vec<ManifestItem> = child(root, "manifest")
+        .map(|m| {
+            m.children()
+                .filter(|n| n.is_element() && n.tag_name().name() == "item")
+                .filter_map(|n| {
+                    Some(ManifestItem {
+                        id: n.attribute("id")?,
+                        path: resolve_href(base, n.attribute("href")?),
+                        media_type: n.attribute("media-type").unwrap_or(""),
+                        properties: n.attribute("properties").unwrap_or("")
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let spine_node = child(root, "spine");
+    // EPUB2 通过 spine 的 toc 属性指向 NCX
+    let toc_id = spine_node.and_then(|s| s.attribute("toc"));
+
+    let navigation: Vec<String> = manifest.iter()
+        .filter(|item| item.is_nav() || item.is_ncx() || Some(item.id) == toc_id)
+        .map(|item| item.path.clone())
+        .collect();
+
+    let spine = spine_node
+        .map(|s| {
+            s.children()
+                .filter(|n| n.is_element() && n.tag_name().name() == "itemref")
+                .filter_map(|n| n.attribute("idref"))
+                .filter_map(|idref| manifest.iter().find(|item| item.id == idref))
+                .filter(|item| item.is_content_document() && !navigation.contains(&item.path))
+                .map(|item| item.path.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let front_matter = child(root, "guide")
+        .map(|g| {
+            g.children()
+                .filter(|n| n.is_element() && n.tag_name().name() == "reference")
+                .filter(|n| n.attribute("type").is_some_and(is_front_matter_type))
+                .filter_map(|n| n.attribute("href"))
+                .map(|href| resolve_href(base, href))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let metadata = child(root, "metadata")
+        .map(parse_metadata)
+        .unwrap_or_default();
+
+    Ok(Package { spine, navigation, front_matter, metadata })
+}
+
+
+fn parse_metadata(node: Node) -> Metadata
+{
+    let texts = |name: &str| -> Vec<String> {
+        node.children()
+            .filter(|n| n.is_element() && n.tag_name().name() == name)
+            .filter_map(|n| n.text())
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect()
+    };
+
+    Metadata {
+        title: texts("title").into_iter().next(),
+        creators: texts("creator"),
+        language: texts("language").into_iter().next(),
+        description: texts("description").into_iter().next()
+    }
+}
+
+
+/// guide/landmarks 的类型是否属于前后附文，不区分大小写
+pub fn is_front_matter_type(kind: &str) -> bool
+{
+    kind.split_whitespace().any(|k| FRONT_MATTER_TYPES.iter().any(|t| t.eq_ignore_ascii_case(k)))
+}
+
+
+/// 将相对于 OPF 的 href 解析为 zip 内的完整路径（处理 `..`、`.`、锚点与百分号编码）
+pub(crate) fn resolve_href(base: &str, href: &str) -> String
+{
+    let href = href.split('#').next().unwrap_or(href);
+    let joined = format!("{}{}", base, percent_decode(href));
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in joined.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            p => parts.push(p)
+        }
+    }
+    parts.join("/")
+}
+
+
+fn percent_decode(s: &str) -> String
+{
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        if let (b'%', Some(b)) = (bytes[i], hex) {
+            out.push(b);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parse_package_excludes_navigation()
+    {
+        let opf = r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>测试</dc:title></metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+    <item id="c1" href="text/ch1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="c2" href="text/ch2.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine toc="ncx">
+    <itemref idref="nav"/>
+    <itemref idref="c1"/>
+    <itemref idref="c2"/>
+  </spine>
+</package>"#;
+        let package = parse_package(opf, "OEBPS/content.opf").unwrap();
+        assert_eq!(package.spine, ["OEBPS/text/ch1.xhtml", "OEBPS/text/ch2.xhtml"]);
+        assert!(package.is_navigation("OEBPS/nav.xhtml"));
+        assert!(package.is_navigation("OEBPS/toc.ncx"));
+        assert!(!package.is_navigation("OEBPS/text/ch1.xhtml"));
+        assert_eq!(package.metadata.title.as_deref(), Some("测试"));
+    }
+}
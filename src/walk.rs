@@ -0,0 +1,318 @@
+//! 目录遍历与文件筛选
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+use rayon::prelude::*;
+use walkdir::{DirEntry, WalkDir};
+
+
+/// 遍历目录时的筛选条件
+pub struct WalkOptions
+{
+    /// 需要统计的扩展名，不区分大小写，可以包含多段（如 `kepub.epub`）
+    pub extensions: Vec<String>,
+    /// 排除匹配这些 glob 的文件
+    pub exclude: Option<ExcludeSet>,
+    /// 最大遍历深度，1 表示只统计目录下的直接文件，`None` 表示不限制
+    pub max_depth: Option<usize>,
+    /// 跟随符号链接，链接成环时会报告错误而不会无限遍历
+    pub follow_symlinks: bool,
+    /// 多线程并行遍历各个子目录，适合网络文件系统等延迟较高的场景
+    pub parallel: bool,
+    /// 遵循遍历根目录下 `.epubignore` 中的规则（语法同 `.gitignore`）
+    pub use_ignore_file: bool,
+    /// 只保留修改时间不早于此时刻的文件
+    pub modified_after: Option<SystemTime>,
+    /// 只保留修改时间早于此时刻的文件
+    pub modified_before: Option<SystemTime>
+}
+
+impl Default for WalkOptions
+{
+    fn default() -> Self
+    {
+        WalkOptions {
+            extensions: vec!["epub".to_string()],
+            exclude: None,
+            max_depth: None,
+            follow_symlinks: false,
+            parallel: false,
+            use_ignore_file: true,
+            modified_after: None,
+            modified_before: None
+        }
+    }
+}
+
+impl WalkOptions
+{
+    /// 文件的修改时间是否落在 `modified_after..modified_before` 内，未设置时间范围时不读取元数据
+    fn modified_in_range(&self, path: &Path) -> bool
+    {
+        if self.modified_after.is_none() && self.modified_before.is_none() {
+            return true;
+        }
+        match fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(mtime) => {
+                self.modified_after.is_none_or(|t| mtime >= t) && self.modified_before.is_none_or(|t| mtime < t)
+            }
+            Err(err) => {
+                warn!("读取 {} 的修改时间时出现错误：{}", path.display(), err);
+                false
+            }
+        }
+    }
+}
+
+
+/// `--exclude` 的 glob 集合
+///
+/// 某个文件的完整路径匹配任意一个模式，或其文件名不区分大小写地匹配任意一个模式时即被排除。
+pub struct ExcludeSet
+{
+    path: GlobSet,
+    filename: GlobSet
+}
+
+impl ExcludeSet
+{
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self>
+    {
+        let mut path = GlobSetBuilder::new();
+        let mut filename = GlobSetBuilder::new();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let glob = |case_insensitive| {
+                GlobBuilder::new(pattern)
+                    .case_insensitive(case_insensitive)
+                    .build()
+                    .with_context(|| format!("无效的 glob 模式：{}", pattern))
+            };
+            path.add(glob(false)?);
+            filename.add(glob(true)?);
+        }
+        Ok(ExcludeSet {
+            path: path.build().context("构建 glob 集合时出现错误")?,
+            filename: filename.build().context("构建 glob 集合时出现错误")?
+        })
+    }
+
+    pub fn is_match<P: AsRef<Path>>(&self, path: P) -> bool
+    {
+        let path = path.as_ref();
+        self.path.is_match(path)
+            || path.file_name().is_some_and(|name| self.filename.is_match(name))
+    }
+}
+
+
+/// 递归遍历目录，返回其中所有 `.epub` 文件的路径
+pub fn get_all_epub_walkdir<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
+    get_all_books_walkdir(path, &WalkOptions::default())
+}
+
+
+/// 忽略规则文件名，放在遍历的根目录下
+pub const IGNORE_FILE: &str = ".epubignore";
+
+
+/// 读取根目录下的 `.epubignore`，不存在或未启用时返回 `None`
+fn load_ignore(root: &Path, options: &WalkOptions) -> Option<Gitignore>
+{
+    let path = root.join(IGNORE_FILE);
+    if !options.use_ignore_file || !path.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(err) = builder.add(&path) {
+        warn!("读取 {} 时出现错误：{}", path.display(), err);
+    }
+    match builder.build() {
+        Ok(ignore) => Some(ignore),
+        Err(err) => {
+            warn!("解析 {} 时出现错误：{}", path.display(), err);
+            None
+        }
+    }
+}
+
+
+/// 路径是否被 `.epubignore` 排除
+fn is_ignored(ignore: Option<&Gitignore>, path: &Path, is_dir: bool) -> bool
+{
+    ignore.is_some_and(|ig| ig.matched_path_or_any_parents(path, is_dir).is_ignore())
+}
+
+
+/// 文件名是否以 `.{ext}` 结尾（不区分大小写）
+pub(crate) fn has_extension(path: &Path, extensions: &[String]) -> bool
+{
+    // 按字节比较，文件名不是有效的 UTF-8 时也能匹配
+    let Some(name) = path.file_name().map(|n| n.as_encoded_bytes()) else {
+        return false;
+    };
+    extensions.iter().any(|ext| {
+        let ext = ext.trim_start_matches('.').as_bytes();
+        name.len() > ext.len() + 1
+            && name[name.len() - ext.len()..].eq_ignore_ascii_case(ext)
+            && name[name.len() - ext.len() - 1] == b'.'
+    })
+}
+
+
+/// 递归遍历目录，返回所有满足 `options` 的文件路径
+pub fn get_all_books_walkdir<P: AsRef<Path>>(path: P, options: &WalkOptions) -> Vec<PathBuf> {
+    let path = path.as_ref();
+    let ignore = load_ignore(path, options);
+    if options.parallel && path.is_dir() {
+        let root = if options.follow_symlinks { fs::canonicalize(path).ok() } else { None };
+        return walk_parallel(path, 0, &root.into_iter().collect::<Vec<_>>(), ignore.as_ref(), options);
+    }
+
+    let is_book = |entry: &DirEntry| -> bool {
+        entry.file_type().is_file()
+            && has_extension(entry.path(), &options.extensions)
+            && !options.exclude.as_ref().is_some_and(|ex| ex.is_match(entry.path()))
+            && !is_ignored(ignore.as_ref(), entry.path(), false)
+            && options.modified_in_range(entry.path())
+    };
+
+    let mut walker = WalkDir::new(path).follow_links(options.follow_symlinks);
+    if let Some(depth) = options.max_depth {
+        walker = walker.max_depth(depth);
+    }
+
+    walker
+        .into_iter()
+        .filter_entry(|e| {
+            (e.file_type().is_dir() && (e.depth() == 0 || !is_ignored(ignore.as_ref(), e.path(), true))) || is_book(e)
+        })
+        .filter_map(|e| match e {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                warn!("遍历目录时出现错误：{}", err);
+                None
+            }
+        })
+        .filter(is_book)
+        .map(|e| e.into_path())
+        .collect()
+}
+
+
+/// 并行遍历 `dir`（深度为 `depth`），每个子目录交给 rayon 的一个任务
+///
+/// 结果按目录项的读取顺序排列，与单线程遍历一致。
+/// `ancestors` 为跟随符号链接时从根目录到 `dir` 的规范路径，用于发现链接成环。
+fn walk_parallel(
+    dir: &Path,
+    depth: usize,
+    ancestors: &[PathBuf],
+    ignore: Option<&Gitignore>,
+    options: &WalkOptions
+) -> Vec<PathBuf>
+{
+    if options.max_depth.is_some_and(|max| depth >= max) {
+        return Vec::new();
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("遍历目录时出现错误：{}：{}", dir.display(), err);
+            return Vec::new();
+        }
+    };
+
+    // `true` 表示子目录，`false` 表示符合条件的文件
+    let mut items = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("遍历目录时出现错误：{}：{}", dir.display(), err);
+                continue;
+            }
+        };
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(t) if t.is_symlink() && options.follow_symlinks => fs::metadata(&path).map(|m| m.file_type()),
+            other => other
+        };
+        match file_type {
+            Ok(t) if t.is_dir() && !is_ignored(ignore, &path, true) => items.push((path, true)),
+            Ok(t) if t.is_file()
+                && has_extension(&path, &options.extensions)
+                && !options.exclude.as_ref().is_some_and(|ex| ex.is_match(&path))
+                && !is_ignored(ignore, &path, false)
+                && options.modified_in_range(&path) => items.push((path, false)),
+            Ok(_) => {}
+            Err(err) => warn!("遍历目录时出现错误：{}：{}", path.display(), err)
+        }
+    }
+
+    items
+        .into_par_iter()
+        .map(|(path, is_dir)| {
+            if !is_dir {
+                return vec![path];
+            }
+            if !options.follow_symlinks {
+                return walk_parallel(&path, depth + 1, ancestors, ignore, options);
+            }
+            match fs::canonicalize(&path) {
+                Ok(real) if ancestors.contains(&real) => {
+                    warn!("遍历目录时出现错误：{} 处的符号链接成环", path.display());
+                    Vec::new()
+                }
+                Ok(real) => {
+                    let mut chain = ancestors.to_vec();
+                    chain.push(real);
+                    walk_parallel(&path, depth + 1, &chain, ignore, options)
+                }
+                Err(err) => {
+                    warn!("遍历目录时出现错误：{}：{}", path.display(), err);
+                    Vec::new()
+                }
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn has_extension_ignores_case()
+    {
+        let extensions = vec!["epub".to_string(), ".txt".to_string()];
+        assert!(has_extension(Path::new("books/a.EPUB"), &extensions));
+        assert!(has_extension(Path::new("b.kepub.epub"), &extensions));
+        assert!(has_extension(Path::new("c.txt"), &extensions));
+        assert!(!has_extension(Path::new(".epub"), &extensions));
+        assert!(!has_extension(Path::new("aepub"), &extensions));
+        assert!(!has_extension(Path::new("a.pdf"), &extensions));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn has_extension_accepts_non_utf8_names()
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let extensions = vec!["epub".to_string()];
+        let name = OsStr::from_bytes(b"\xc4\xe3\xba\xc3.epub");
+        assert!(name.to_str().is_none());
+        assert!(has_extension(&Path::new("books").join(name), &extensions));
+    }
+}
@@ -0,0 +1,97 @@
+//! `--watch`：输入文件变化时重新统计
+//!
+//! 每次统计都在子进程中完成（去掉 `--watch` 后重新运行自身），
+//! 全局线程池、中断标志等进程级状态每次都是新的。
+
+use std::env;
+use std::ffi::OsString;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+
+/// 最后一次变化之后这么久没有新的变化才重新统计，避免一次保存触发多次统计
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+
+/// 事件是否表示文件内容可能变了
+///
+/// 统计时读取文件也会产生打开、访问事件，这些必须忽略，否则会不停地重新统计。
+fn is_change(kind: &EventKind) -> bool
+{
+    match kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        _ => false
+    }
+}
+
+
+/// `path` 是否为需要统计的文件：扩展名在 `extensions` 中，或者是命令行上直接给出的文件
+fn is_relevant(path: &Path, files: &[PathBuf], extensions: &[String]) -> bool
+{
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let lower = name.to_string_lossy().to_ascii_lowercase();
+    files.iter().any(|f| f.file_name() == Some(name))
+        || extensions.iter().any(|ext| lower.ends_with(&format!(".{}", ext.trim_start_matches('.').to_ascii_lowercase())))
+}
+
+
+/// 先统计一次，之后每当 `inputs` 中的文件（或目录下扩展名为 `extensions` 的文件）变化就清屏并重新统计
+///
+/// 正常情况下不会返回；监视失败时返回错误。
+pub fn run(inputs: &[String], recursive: bool, extensions: &[String]) -> Result<()>
+{
+    let exe = env::current_exe().context("找不到当前程序的路径")?;
+    let args: Vec<OsString> = env::args_os().skip(1).filter(|a| a != "--watch").collect();
+    let recount = || {
+        print!("\x1b[2J\x1b[H");
+        let _ = stdout().flush();
+        if let Err(e) = Command::new(&exe).args(&args).status() {
+            eprintln!("重新统计时出现错误：{}", e);
+        }
+    };
+
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(tx).context("创建文件监视器时出现错误")?;
+    let mut files = Vec::new();
+    for input in inputs.iter().filter(|i| !i.starts_with("http://") && !i.starts_with("https://")) {
+        let path = PathBuf::from(input);
+        if path.is_dir() {
+            let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+            watcher.watch(&path, mode).with_context(|| format!("监视 {} 时出现错误", path.display()))?;
+        } else {
+            // 很多程序保存时先写临时文件再改名，只有监视所在目录才能收到新文件的事件
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            watcher.watch(parent, RecursiveMode::NonRecursive)
+                .with_context(|| format!("监视 {} 时出现错误", parent.display()))?;
+            files.push(path);
+        }
+    }
+
+    recount();
+    loop {
+        let event = match rx.recv().context("文件监视器已停止")? {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("监视文件时出现错误：{}", e);
+                continue;
+            }
+        };
+        if !is_change(&event.kind) || !event.paths.iter().any(|p| is_relevant(p, &files, extensions)) {
+            continue;
+        }
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+        recount();
+    }
+}